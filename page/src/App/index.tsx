@@ -14,16 +14,53 @@ import './index.css'
 export default function App() {
     const [messages, setMessages] = React.useState<Message[]>([])
     const [selected, setSelected] = React.useState<Message | null>(null)
+    const [search, setSearch] = React.useState('')
 
     React.useEffect(() => {
-        loadMessages().then(messages => setMessages(messages))
+        const query = search === '' ? {} : { q: search }
+        // Search is done on the server, so list is reloaded when it changes.
+        const timeout = setTimeout(
+            () => loadMessages(query).then(messages => setMessages(messages)),
+            search === '' ? 0 : 300,
+        )
 
-        return subscribe(message => setMessages(messages => [...messages, message]))
-    }, [setMessages])
-    console.log(messages)
+        const unsubscribe = subscribe({
+            onMessage: message => {
+                if (search === '') {
+                    setMessages(messages => [...messages, message])
+                } else {
+                    loadMessages(query).then(messages => setMessages(messages))
+                }
+            },
+            onDelete: ids => {
+                setMessages(messages => messages.filter(message => !ids.includes(message.id)))
+                setSelected(selected => selected != null && ids.includes(selected.id)
+                    ? null : selected)
+            },
+        })
+
+        return () => {
+            clearTimeout(timeout)
+            unsubscribe()
+        }
+    }, [search, setMessages, setSelected])
+
+    const onSearch = React.useCallback(
+        (ev: React.ChangeEvent<HTMLInputElement>) => setSearch(ev.target.value),
+        [setSearch],
+    )
 
     return <>
-        <MailList messages={messages} onSelect={setSelected} />
+        <div className="inbox">
+            <input
+                className="search"
+                type="search"
+                placeholder="Search messages"
+                value={search}
+                onChange={onSearch}
+                />
+            <MailList messages={messages} onSelect={setSelected} />
+        </div>
         {selected != null && <MailView message={selected} />}
     </>
 }
@@ -63,7 +63,11 @@ function Item({ message, selected, onSelect }: ItemProps) {
     }, [selected, onSelect, message])
 
     return <tr className={selected ? 'selected' : undefined} onClick={onClick}>
-        <td className="subject">{message.subject}</td>
+        <td className="subject">
+            {message.subject}
+            {message.match != null
+                && <div className="snippet">{message.match.snippet}</div>}
+        </td>
         <td className="from">
             <Mailbox format="short" mailbox={message.from[0]} />
         </td>
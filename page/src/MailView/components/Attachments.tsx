@@ -0,0 +1,35 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+import * as React from 'react'
+
+import { Attachment } from '~/src/data'
+
+interface Props {
+    attachments: Attachment[]
+}
+
+/** List of files attached to a message, with links to download them */
+export default function Attachments({ attachments }: Props) {
+    return <ul className="attachments">
+        {attachments.map(attachment => <li key={attachment.part}>
+            <a href={attachment.url} download>
+                {attachment.filename ?? `Part ${attachment.part}`}
+            </a>
+            <span className="size">
+                ({attachment.contentType.split(';', 1)[0]}, {formatSize(attachment.size)})
+            </span>
+        </li>)}
+    </ul>
+}
+
+function formatSize(size: number): string {
+    if (size < 1024) {
+        return `${size} B`
+    } else if (size < 1024 * 1024) {
+        return `${(size / 1024).toFixed(1)} KiB`
+    } else {
+        return `${(size / 1024 / 1024).toFixed(1)} MiB`
+    }
+}
@@ -0,0 +1,38 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+import * as React from 'react'
+
+import { EmbeddedMessage as EmbeddedMessageData, Message } from '~/src/data'
+
+import MultipartPart from './MultipartPart'
+
+/** Header fields shown for an embedded message */
+const FIELDS = ['From', 'To', 'Cc', 'Subject', 'Date']
+
+export interface Props {
+    message: Message
+    part?: string
+    data: EmbeddedMessageData
+}
+
+/** Message embedded in another one, such as a forwarded message */
+export default function EmbeddedMessage({ message, part, data }: Props) {
+    const fields = data.headers.filter(field => FIELDS.some(
+        name => name.toLowerCase() === field.name.toLowerCase()))
+
+    return <div className="embedded-message">
+        <div className="details">
+            {fields.map((field, index) => <React.Fragment key={index}>
+                <span className="field-name">{field.name}</span>
+                <span>{field.value}</span>
+            </React.Fragment>)}
+        </div>
+        <MultipartPart
+            message={message}
+            part={`${part ?? ''}/0`}
+            contentType={data.body.contentType}
+            />
+    </div>
+}
@@ -0,0 +1,32 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+import * as React from 'react'
+
+import { HeaderField, Message, loadHeaders } from '~/src/data'
+
+interface Props {
+    message: Message
+    part?: string
+}
+
+/** List of all header fields of a message or one of its parts */
+export default function Headers({ message, part }: Props) {
+    const [headers, setHeaders] = React.useState<HeaderField[] | null>(null)
+
+    React.useEffect(() => {
+        loadHeaders(message.id, part).then(setHeaders)
+    }, [message.id, part, setHeaders])
+
+    if (headers == null) {
+        return <div>Loading</div>
+    }
+
+    return <div className="headers">
+        {headers.map((field, index) => <React.Fragment key={index}>
+            <span className="field-name">{field.name}</span>
+            <span className="field-value">{field.value}</span>
+        </React.Fragment>)}
+    </div>
+}
@@ -6,6 +6,7 @@ import * as React from 'react'
 
 import { Message, MessageData, loadMessage } from '~/src/data'
 
+import EmbeddedMessage from './EmbeddedMessage'
 import SimpleBody from './SimpleBody'
 import MultipartBody from './MultipartBody'
 
@@ -27,6 +28,8 @@ export default function MessageBody({ message, part }: Props) {
 
     if (typeof body.data === 'string') {
         return <SimpleBody message={message} part={part} data={body} />
+    } else if ('headers' in body.data) {
+        return <EmbeddedMessage message={message} part={part} data={body.data} />
     } else {
         return <MultipartBody message={message} part={part} data={body.data} />
     }
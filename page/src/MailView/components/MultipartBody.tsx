@@ -6,6 +6,8 @@ import { Message, Multipart } from '~/src/data'
 
 import MultipartAlternative from './MultipartAlternative'
 import MultipartMixed from './MultipartMixed'
+import MultipartRelated from './MultipartRelated'
+import MultipartSigned from './MultipartSigned'
 
 export interface Props {
     message: Message
@@ -16,9 +18,23 @@ export interface Props {
 export default function MultipartBody({ message, part, data }: Props) {
     switch (data.kind) {
     case 'mixed':
+    case 'parallel':
+    case 'digest':
+    case 'report':
         return <MultipartMixed message={message} part={part} data={data} />
 
     case 'alternative':
         return <MultipartAlternative message={message} part={part} data={data} />
+
+    case 'related':
+        return <MultipartRelated message={message} part={part} data={data} />
+
+    case 'signed':
+        return <MultipartSigned message={message} part={part} data={data} />
+
+    case 'encrypted':
+        return <div className="multipart encrypted">
+            Encrypted content ({data.protocol ?? 'unknown protocol'})
+        </div>
     }
 }
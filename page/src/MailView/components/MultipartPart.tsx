@@ -13,7 +13,9 @@ export interface Props {
 }
 
 export default function MultipartPart({ message, part, contentType }: Props) {
-    if (contentType.startsWith('text/') || contentType.startsWith('multipart/')) {
+    if (contentType.startsWith('text/')
+    || contentType.startsWith('multipart/')
+    || contentType.startsWith('message/')) {
         return <MessageBody message={message} part={part} />
     }
 
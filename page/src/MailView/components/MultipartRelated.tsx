@@ -0,0 +1,26 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+import { Message, Multipart } from '~/src/data'
+
+import MultipartPart from './MultipartPart'
+
+export interface Props {
+    message: Message
+    part?: string
+    data: Multipart & { kind: 'related' }
+}
+
+/** Compound object, presented through its root part */
+export default function MultipartRelated({ message, part, data }: Props) {
+    const root = data.parts[data.start]
+
+    return <div className="multipart related">
+        <MultipartPart
+            message={message}
+            part={`${part ?? ''}/${data.start}`}
+            contentType={root.contentType}
+            />
+    </div>
+}
@@ -0,0 +1,27 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+import { Message, Multipart } from '~/src/data'
+
+import MultipartPart from './MultipartPart'
+
+export interface Props {
+    message: Message
+    part?: string
+    data: Multipart & { kind: 'signed' }
+}
+
+/** Signed content; the signature itself is not verified */
+export default function MultipartSigned({ message, part, data }: Props) {
+    return <div className="multipart signed">
+        <div className="signature">
+            Signed ({data.protocol ?? 'unknown protocol'})
+        </div>
+        <MultipartPart
+            message={message}
+            part={`${part ?? ''}/0`}
+            contentType={data.parts[0].contentType}
+            />
+    </div>
+}
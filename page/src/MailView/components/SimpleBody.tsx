@@ -15,7 +15,10 @@ interface Props {
 /** Body of a message which is not multi-part */
 export default function SimpleBody({ message, part, data }: Props) {
     if (data.contentType.startsWith('text/html')) {
-        const url = part == null ? `/messages/${message.id}` : `/messages/${message.id}${part}`
+        // Let inline images referenced by cid: URLs load from sibling parts.
+        const url = part == null
+            ? `/messages/${message.id}`
+            : `/messages/${message.id}${part}?resolveCids=true`
         return <Frame src={url} />
     } else {
         return <pre>{data.data}</pre>
@@ -6,9 +6,12 @@ import * as React from 'react'
 
 import DateTime from '~/src/components/DateTime'
 import MailboxList from '~/src/components/MailboxList'
+import Tabs from '~/src/components/Tabs'
 
-import { Message } from '~/src/data'
+import { Attachment, Message, deleteMessage, loadAttachments, rawMessageUrl } from '~/src/data'
 
+import Attachments from './components/Attachments'
+import Headers from './components/Headers'
 import MessageBody from './components/MessageBody'
 
 import './index.css'
@@ -18,22 +21,85 @@ interface Props {
 }
 
 export default function MailView({ message }: Props) {
+    const onDelete = React.useCallback(() => {
+        deleteMessage(message.id)
+    }, [message.id])
+
+    const [attachments, setAttachments] = React.useState<Attachment[]>([])
+
+    React.useEffect(() => {
+        loadAttachments(message.id).then(setAttachments)
+    }, [message.id, setAttachments])
+
+    const renderTab = React.useCallback((index: number) => index === 0
+        ? <MessageTab message={message} />
+        : <Headers message={message} />,
+        [message],
+    )
+
     return <div className="mail-view">
         <div className="details">
             <Field name="From">
                 <MailboxList mailboxes={message.from} />
             </Field>
+            {message.sender != null && <Field name="Sender">
+                <MailboxList mailboxes={[message.sender]} />
+            </Field>}
+            {message.replyTo.length > 0 && <Field name="Reply to">
+                <MailboxList mailboxes={message.replyTo} />
+            </Field>}
             <Field name="Subject" value={message.subject} />
             <Field name="To">
                 <MailboxList mailboxes={message.to} />
             </Field>
+            {message.cc.length > 0 && <Field name="Cc">
+                <MailboxList mailboxes={message.cc} />
+            </Field>}
+            {message.bcc.length > 0 && <Field name="Bcc">
+                <MailboxList mailboxes={message.bcc} />
+            </Field>}
+            {message.inReplyTo.length > 0
+                && <Field name="In reply to" value={message.inReplyTo.map(id => `<${id}>`).join(' ')} />}
+            {message.references.length > 0
+                && <Field name="References" value={message.references.map(id => `<${id}>`).join(' ')} />}
+            {message.keywords.length > 0
+                && <Field name="Keywords" value={message.keywords.join(', ')} />}
+            {message.comments.map((comment, index) =>
+                <Field key={index} name="Comments" value={comment} />)}
             <Field name="Sent">
                 <DateTime format="medium" date={new Date(message.date * 1000)} />
             </Field>
+            <Field name="Envelope from" value={message.envelope.from ?? '<>'} />
+            <Field name="Envelope to" value={message.envelope.to.join(', ')} />
+            <Field
+                name="Client"
+                value={`${message.envelope.client ?? 'unknown'} (${message.envelope.remote})`}
+                />
+            <Field name="Encrypted" value={message.envelope.tls ? 'Yes' : 'No'} />
+            {message.envelope.user != null
+                && <Field name="Authenticated as" value={message.envelope.user} />}
+            {attachments.length > 0 && <Field name="Attachments">
+                <Attachments attachments={attachments} />
+            </Field>}
+            <Field name="Source">
+                <a href={rawMessageUrl(message.id)} download>Download .eml</a>
+            </Field>
+            <Field name="">
+                <button onClick={onDelete}>Delete</button>
+            </Field>
         </div>
-        <div className="body">
-            <MessageBody message={message} />
-        </div>
+        <Tabs tabs={TABS} render={renderTab} />
+    </div>
+}
+
+const TABS = [
+    { title: 'Message', data: null },
+    { title: 'Headers', data: null },
+]
+
+function MessageTab({ message }: { message: Message }) {
+    return <div className="body">
+        <MessageBody message={message} />
     </div>
 }
 
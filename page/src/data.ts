@@ -6,15 +6,83 @@
 export interface Message {
     /** Message ID */
     id: string
+    /** SMTP envelope under which this message was received */
+    envelope: Envelope
     /** Subject */
     subject: string
     /** Sender's email address */
     from: Mailbox[]
+    /** Mailbox of the agent which actually sent this message, if not author */
+    sender: Mailbox | null
+    /** Addresses to which replies should be sent */
+    replyTo: (Mailbox | Group)[]
     /** Addressee's email address */
     to: (Mailbox | Group)[]
+    /** Carbon-copy recipients */
+    cc: (Mailbox | Group)[]
+    /** Blind carbon-copy recipients */
+    bcc: (Mailbox | Group)[]
+    /** IDs of messages to which this one is a reply */
+    inReplyTo: string[]
+    /** IDs of messages in the thread this one belongs to */
+    references: string[]
+    keywords: string[]
+    comments: string[]
     /** Date and time when this message was sent, as a UNIX timestamp */
     date: number,
     body: 'data' | 'mime-multipart',
+    /** Where the searched-for phrase was found, when searching */
+    match?: SearchMatch
+}
+
+export interface SearchMatch {
+    /** Path of the MIME part in which the phrase was found */
+    part?: string
+    /** Fragment of text surrounding the match */
+    snippet: string
+}
+
+export interface Envelope {
+    /** Name client introduced itself with in EHLO/HELO */
+    client: string | null
+    /** Address and port of the client */
+    remote: string
+    /** Was this message received over an encrypted connection? */
+    tls: boolean
+    /** Name of the user client has authenticated as */
+    user: string | null
+    /** Reverse path (MAIL FROM), or null for a null reverse path */
+    from: string | null
+    /** Forward paths (RCPT TO) */
+    to: string[]
+    /** Time at which the message was received, as a UNIX timestamp */
+    received: number
+    /** Delivery status notification parameters, if client provided any */
+    dsn?: Dsn
+}
+
+export interface Dsn {
+    /** How much of the message should be returned in a failure notification */
+    ret?: 'full' | 'headers'
+    /** Envelope identifier (ENVID), still xtext-encoded */
+    envelopeId?: string
+    /** Parameters of each recipient, in the same order as Envelope.to */
+    recipients: DsnRecipient[]
+}
+
+export interface DsnRecipient {
+    /** Conditions under which notifications should be sent, all false for NEVER */
+    notify?: {
+        success: boolean
+        failure: boolean
+        delay: boolean
+    }
+    /** Original recipient address (ORCPT) */
+    original?: {
+        addressType: string
+        /** Address, still xtext-encoded */
+        address: string
+    }
 }
 
 export interface Group {
@@ -32,48 +100,184 @@ export interface Address {
     domain: string
 }
 
+/** Change to the list of messages, as sent over {@link subscribe} */
+export type Notification =
+    | { type: 'message' } & Message
+    | { type: 'deleted', ids: string[] }
+
+export interface Listeners {
+    /** Called when a new message arrives */
+    onMessage: (message: Message) => void
+    /** Called when messages are deleted */
+    onDelete: (ids: string[]) => void
+}
+
 /**
- * Subscribe to new messages, calling {@code onMessage} when a message arrives
+ * Subscribe to changes in the list of messages
  */
-export function subscribe(onMessage: (message: Message) => void): () => void {
+export function subscribe({ onMessage, onDelete }: Listeners): () => void {
     const ws = new WebSocket(`ws://${location.host}/subscribe`)
 
     ws.onclose = () => console.log('connection closed')
     ws.onerror = ev => console.log('connection error:', ev)
     ws.onopen = () => console.log('connection established')
     ws.onmessage = ev => {
-        const message = JSON.parse(ev.data)
-        console.log('new message:', message)
-        onMessage(message)
+        const notification: Notification = JSON.parse(ev.data)
+        console.log('notification:', notification)
+
+        switch (notification.type) {
+        case 'message': {
+            const { type, ...message } = notification
+            onMessage(message)
+            break
+        }
+
+        case 'deleted':
+            onDelete(notification.ids)
+            break
+        }
     }
 
     return () => ws.close()
 }
 
+/** Parameters for filtering, sorting, and paginating list of messages */
+export interface MessageQuery {
+    /** Phrase which must appear anywhere in the message */
+    q?: string
+    /** Sender's address, in the From header or in the envelope */
+    from?: string
+    /** Recipient's address, in the To, Cc, or Bcc header, or in the envelope */
+    to?: string
+    /** Text which must appear in the subject */
+    subject?: string
+    /** Regular expression which must match the subject */
+    subjectRegex?: string
+    /** Only messages sent at or after this time, as a UNIX timestamp */
+    since?: number
+    /** Only messages sent at or before this time, as a UNIX timestamp */
+    until?: number
+    sort?: 'date' | '-date'
+    limit?: number
+    offset?: number
+}
+
 /** Load list of messages */
-export async function loadMessages(): Promise<Message[]> {
-    const rsp = await fetch('/messages')
+export async function loadMessages(query: MessageQuery = {}): Promise<Message[]> {
+    const params = new URLSearchParams()
+    for (const [key, value] of Object.entries(query)) {
+        if (value != null) {
+            params.set(key, String(value))
+        }
+    }
+
+    const rsp = await fetch(`/messages?${params}`)
     return await rsp.json()
 }
 
+/** Delete a single message */
+export async function deleteMessage(id: string): Promise<void> {
+    await fetch(`/messages/${encodeURIComponent(id)}`, { method: 'DELETE' })
+}
+
+/** Delete all messages */
+export async function clearMessages(): Promise<void> {
+    await fetch('/messages', { method: 'DELETE' })
+}
+
 export interface MessageData {
     contentType: string
-    data: string | Multipart
+    data: string | Multipart | EmbeddedMessage
 }
 
-export interface Multipart {
-    kind: 'mixed' | 'alternative'
-    parts: Part[]
-}
+export type Multipart = { parts: Part[] } & (
+    | { kind: 'mixed' | 'alternative' | 'parallel' | 'digest' }
+    | {
+        kind: 'related'
+        /** Index of the root part */
+        start: number
+        /** Content type of the root part */
+        type: string | null
+    }
+    | { kind: 'report', reportType: string | null }
+    | { kind: 'signed', protocol: string | null, micalg: string | null }
+    | { kind: 'encrypted', protocol: string | null }
+)
 
 export interface Part {
     contentType: string
+    /** Value of the Content-ID header, without angle brackets */
+    contentId?: string
+    /** Value of the Content-Description header */
+    description?: string
+    /** Suggested file name */
+    filename?: string
+    disposition?: Disposition
+}
+
+/** Presentation information from the Content-Disposition header */
+export interface Disposition {
+    /** Disposition type, such as inline or attachment */
+    type: string
+    /** Creation date of the file, as a UNIX timestamp */
+    created?: number
+    /** Last modification date of the file, as a UNIX timestamp */
+    modified?: number
+    /** Last access date of the file, as a UNIX timestamp */
+    read?: number
+    /** Approximate size of the file, in octets */
+    size?: number
+}
+
+/** Message embedded in another one, as a message/rfc822 part */
+export interface EmbeddedMessage {
+    headers: HeaderField[]
+    /** The only part of an embedded message */
+    body: Part
 }
 
 export function messageUrl(id: string, part?: string): string {
     return part == null
-        ? `/messages/${id}`
-        : `/messages/${id}/${part}`
+        ? `/messages/${encodeURIComponent(id)}`
+        : `/messages/${encodeURIComponent(id)}/${part}`
+}
+
+/** URL from which original message can be downloaded */
+export function rawMessageUrl(id: string): string {
+    return `/messages/${encodeURIComponent(id)}/raw`
+}
+
+/** Single header field */
+export interface HeaderField {
+    name: string
+    /** Unfolded value, with encoded words decoded */
+    value: string
+}
+
+/** Load all header fields of a message, or of one of its parts */
+export async function loadHeaders(id: string, part?: string): Promise<HeaderField[]> {
+    const rsp = await fetch(part == null
+        ? `/messages/${encodeURIComponent(id)}/headers`
+        : `/messages/${encodeURIComponent(id)}${part}/headers`)
+    return await rsp.json()
+}
+
+/** File attached to a message */
+export interface Attachment {
+    /** Path of the MIME part containing this file */
+    part: string
+    filename: string | null
+    contentType: string
+    /** Size of the file, in octets */
+    size: number
+    /** URL from which the file can be downloaded */
+    url: string
+}
+
+/** Load list of files attached to a message */
+export async function loadAttachments(id: string): Promise<Attachment[]> {
+    const rsp = await fetch(`/messages/${encodeURIComponent(id)}/attachments`)
+    return await rsp.json()
 }
 
 export async function loadMessage(id: string, part?: string): Promise<MessageData> {
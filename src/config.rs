@@ -5,33 +5,121 @@
 use anyhow::Result;
 use argh::FromArgs;
 use serde::Deserialize;
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::PathBuf};
 
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
     pub smtp: Smtp,
     pub http: Http,
+    #[serde(default)]
+    pub storage: Storage,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Smtp {
-    pub port: u16,
+    pub listeners: Vec<Listener>,
+    /// Port of a single plain listener, replacing [`Smtp::listeners`]
+    ///
+    /// Deprecated, kept so that configurations written before listeners were
+    /// introduced continue to work.
+    pub port: Option<u16>,
     pub message_size: usize,
+    pub tls: Tls,
+    pub auth: Auth,
+    /// Generate delivery reports requested with DSN parameters (RFC 3461)
+    pub delivery_reports: bool,
 }
 
 impl Default for Smtp {
     fn default() -> Self {
         Smtp {
             // RFC 6409 specifies 587 as the SMTP TCP port
-            port: 587,
+            listeners: vec![Listener {
+                port: 587,
+                mode: ListenerMode::StartTls,
+                parsing: Parsing::default(),
+            }],
+            port: None,
             // RFC 5321 section 4.5.3.1.7 specified 64k octets as smallest
             // allowed upper limit on message length.
             message_size: 64 * 1024,
+            tls: Tls::default(),
+            auth: Auth::default(),
+            delivery_reports: false,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Listener {
+    pub port: u16,
+    #[serde(default)]
+    pub mode: ListenerMode,
+    #[serde(default)]
+    pub parsing: Parsing,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListenerMode {
+    /// Unencrypted connections only
+    Plain,
+    /// Unencrypted connections which can be upgraded using STARTTLS
+    #[default]
+    #[serde(rename = "starttls")]
+    StartTls,
+    /// Connections are encrypted from the start (RFC 8314)
+    ImplicitTls,
+}
+
+/// How to handle messages which can't be parsed
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Parsing {
+    /// Reject malformed messages with an SMTP error
+    #[default]
+    Strict,
+    /// Accept and store all messages, recording problems in their list of
+    /// errors
+    Lenient,
+}
+
+/// TLS configuration
+///
+/// When neither certificate nor key are specified a self-signed certificate
+/// will be generated on startup.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Tls {
+    /// Path to a PEM file containing certificate chain
+    pub certificate: Option<PathBuf>,
+    /// Path to a PEM file containing private key
+    pub key: Option<PathBuf>,
+}
+
+/// SMTP AUTH configuration
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Auth {
+    pub policy: AuthPolicy,
+    /// Passwords of users, keyed by username
+    pub users: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthPolicy {
+    /// Accept any credentials
+    #[default]
+    AcceptAny,
+    /// Accept only credentials of users listed in [`Auth::users`]
+    Users,
+    /// Reject all credentials
+    Reject,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Http {
     pub port: u16,
@@ -43,6 +131,22 @@ impl Default for Http {
     }
 }
 
+/// Message storage configuration
+///
+/// The `backend` key selects the variant and is required whenever the
+/// `[storage]` table is present; an omitted table means [`Storage::Memory`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub enum Storage {
+    /// Keep messages in memory only, losing them on restart
+    #[default]
+    Memory,
+    /// Save messages in a directory and reload them on startup
+    Disk {
+        path: PathBuf,
+    },
+}
+
 /// SMTP test server
 #[derive(FromArgs)]
 struct Args {
@@ -52,7 +156,7 @@ struct Args {
     /// port to run HTTP server on
     #[argh(option)]
     http_port: Option<u16>,
-    /// port to run SMTP server on
+    /// port to run a plain SMTP server on, replaces listeners from configuration
     #[argh(option)]
     smtp_port: Option<u16>,
 }
@@ -68,12 +172,25 @@ pub fn load() -> Result<Config> {
         }
     };
 
+    if let Some(port) = config.smtp.port.take() {
+        log::warn!("smtp.port is deprecated, use [[smtp.listeners]] instead");
+        config.smtp.listeners = vec![Listener {
+            port,
+            mode: ListenerMode::Plain,
+            parsing: Parsing::default(),
+        }];
+    }
+
     if let Some(port) = args.http_port {
         config.http.port = port;
     }
 
     if let Some(port) = args.smtp_port {
-        config.smtp.port = port;
+        config.smtp.listeners = vec![Listener {
+            port,
+            mode: ListenerMode::Plain,
+            parsing: Parsing::default(),
+        }];
     }
 
     Ok(config)
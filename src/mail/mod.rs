@@ -6,23 +6,37 @@
 //! https://datatracker.ietf.org/doc/html/rfc5322): Internet Message Format
 
 use memchr::memmem;
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::{syntax::*, mime, util::SetOnce, state::Errors};
-use self::syntax::{Header, MailboxList, MailboxRef, PathRef, Received, AnyDateTime, AddressOrGroupList};
+use self::syntax::{
+    Folded, Header, MailboxList, MailboxRef, PathRef, Received, AnyDateTime, AddressOrGroupList,
+    MessageIdList,
+};
 
-pub use self::syntax::{Address, AddressOrGroup, Mailbox};
+pub use self::syntax::{Address, AddressOrGroup, Mailbox, Path};
 
 pub mod syntax;
 
 pub struct ParsedMessage<'a> {
+    /// All header fields, in order in which they appear
+    pub headers: Vec<HeaderField>,
     pub trace: Vec<Trace<'a>>,
     pub id: Option<String>,
-    pub origination_date: AnyDateTime,
+    /// Only `None` when parsing leniently
+    pub origination_date: Option<AnyDateTime>,
     pub from: MailboxList<'a>,
     pub sender: Option<MailboxRef<'a>>,
+    pub reply_to: AddressOrGroupList<'a>,
     pub to: AddressOrGroupList<'a>,
+    pub cc: AddressOrGroupList<'a>,
+    pub bcc: AddressOrGroupList<'a>,
+    pub in_reply_to: MessageIdList<'a>,
+    pub references: MessageIdList<'a>,
     pub subject: Option<String>,
+    pub comments: Vec<String>,
+    pub keywords: Vec<String>,
     pub body: Body<'a>,
 }
 
@@ -33,7 +47,7 @@ pub struct Trace<'a> {
 }
 
 pub struct ResentInfo<'a> {
-    pub date: AnyDateTime,
+    pub date: Option<AnyDateTime>,
     pub from: MailboxList<'a>,
     pub sender: Option<MailboxRef<'a>>,
     pub to: AddressOrGroupList<'a>,
@@ -42,6 +56,14 @@ pub struct ResentInfo<'a> {
     pub id: Option<String>,
 }
 
+/// Single header field, as it appears in a message
+#[derive(Clone, Debug, Serialize)]
+pub struct HeaderField {
+    pub name: String,
+    /// Unfolded value, with encoded words decoded
+    pub value: String,
+}
+
 /// Message body
 pub enum Body<'a> {
     /// Unknown format
@@ -59,11 +81,12 @@ pub struct ParseFieldError<'a, E> {
 }
 
 pub fn parse<'a>(message: &'a [u8], errors: &mut Errors) -> Result<ParsedMessage<'a>> {
-    let (header, body) = separate_message(message);
-    let mut header = Buffer::new(header);
+    let (header_data, body) = separate_message(message);
+    let headers = header_fields(header_data);
+    let mut header = Buffer::new(header_data);
 
     let mut trace = vec![];
-    while let Some(item) = parse_trace(&mut header)? {
+    while let Some(item) = parse_trace(&mut header, errors)? {
         trace.push(item);
     }
 
@@ -85,65 +108,107 @@ pub fn parse<'a>(message: &'a [u8], errors: &mut Errors) -> Result<ParsedMessage
     let mut transfer_encoding = None;
     let mut content_id = None;
     let mut content_description = None;
+    let mut content_disposition = None;
 
     while !header.is_empty() {
         let location = header.location();
+        let start = header;
 
         let field = match syntax::field(&mut header) {
             Ok(field) => field,
             Err(error) => {
-                let (field, _) = syntax::optional_field(&mut header)?;
-                errors.add(error.map(|error| ParseFieldError { field, error }));
+                match syntax::optional_field(&mut header) {
+                    Ok((field, _)) =>
+                        errors.add(error.map(|error| ParseFieldError { field, error })),
+                    Err(error) => {
+                        errors.recover(error)?;
+                        skip_field(&mut header);
+                    }
+                }
                 continue;
             }
         };
 
         match field {
             Header::OriginationDate(value) =>
-                origination_date.set_once(location, "Origination-Date", value)?,
+                origination_date.set_once(errors, location, "Origination-Date", value)?,
             Header::From(value) =>
-                from.set_once(location, "From", value)?,
+                from.set_once(errors, location, "From", value)?,
             Header::Sender(value) =>
-                sender.set_once(location, "Sender", value)?,
+                sender.set_once(errors, location, "Sender", value)?,
             Header::ReplyTo(value) =>
-                reply_to.set_once(location, "Reply-To", value)?,
+                reply_to.set_once(errors, location, "Reply-To", value)?,
             Header::To(value) =>
-                to.set_once(location, "To", value)?,
+                to.set_once(errors, location, "To", value)?,
             Header::CarbonCopy(value) =>
-                cc.set_once(location, "Carbon-Copy", value)?,
+                cc.set_once(errors, location, "Carbon-Copy", value)?,
             Header::BlindCarbonCopy(value) =>
-                bcc.set_once(location, "Blind-Carbon-Copy", value)?,
+                bcc.set_once(errors, location, "Blind-Carbon-Copy", value)?,
             Header::MessageId(value) =>
-                id.set_once(location, "Message-ID", value.0.into())?,
+                id.set_once(errors, location, "Message-ID", value.0.into())?,
             Header::InReplyTo(value) =>
-                in_reply_to.set_once(location, "InReply-To", value)?,
+                in_reply_to.set_once(errors, location, "In-Reply-To", value)?,
             Header::References(value) =>
-                references.set_once(location, "References", value)?,
+                references.set_once(errors, location, "References", value)?,
             Header::Subject(value) =>
-                subject.set_once(location, "Subject", value.unfold())?,
+                subject.set_once(errors, location, "Subject", value.unfold())?,
             Header::Comments(value) => comments.push(value.unfold()),
             Header::Keywords(value) =>
                 keywords.extend(value.iter().map(|keyword| keyword.unquote())),
-            Header::ResentDate(_) => todo!(),
-            Header::ResentFrom(_) => todo!(),
-            Header::ResentSender(_) => todo!(),
-            Header::ResentTo(_) => todo!(),
-            Header::ResentCarbonCopy(_) => todo!(),
-            Header::ResentBlindCarbonCopy(_) => todo!(),
-            Header::ResentMessageId(_) => todo!(),
-            Header::ReturnPath(_) => todo!(),
-            Header::Received(_) => todo!(),
+            // Trace fields should only appear at the beginning of the header,
+            // but agents relaying or forwarding messages don't always put them
+            // there.
+            Header::ReturnPath(value) => {
+                errors.add_at(location, "trace field outside of the trace block");
+                trace.push(Trace {
+                    return_path: Some(value),
+                    received: ListOf::empty(),
+                    resending: vec![],
+                });
+            }
+            Header::Received(_) => {
+                errors.add_at(location, "trace field outside of the trace block");
+                // Parsed again from its own data, so that it can be kept
+                // alongside other received fields.
+                let mut cursor = start;
+                let data = cursor.take(start.len() - header.len());
+                let received = Buffer::new(data).list_of::<Received>(1, 1, b"")?;
+                trace.push(Trace {
+                    return_path: None,
+                    received,
+                    resending: vec![],
+                });
+            }
+            Header::ResentDate(_) | Header::ResentFrom(_) | Header::ResentSender(_)
+            | Header::ResentTo(_) | Header::ResentCarbonCopy(_)
+            | Header::ResentBlindCarbonCopy(_) | Header::ResentMessageId(_) => {
+                errors.add_at(location, "trace field outside of the trace block");
+
+                // Fields of a single resent block follow each other, so the
+                // whole block is parsed starting at this field.
+                let mut cursor = start;
+                if let Some(info) = parse_resent_block(&mut cursor, errors)? {
+                    header = cursor;
+                    trace.push(Trace {
+                        return_path: None,
+                        received: ListOf::empty(),
+                        resending: vec![info],
+                    });
+                }
+            }
             Header::Mime(header) => match header {
                 mime::Header::Version(value) =>
-                    mime_version.set_once(location, "MIME-Version", value)?,
+                    mime_version.set_once(errors, location, "MIME-Version", value)?,
                 mime::Header::ContentType(value) =>
-                    content_type.set_once(location, "Content-Type", value)?,
+                    content_type.set_once(errors, location, "Content-Type", Located::new(location, value))?,
                 mime::Header::ContentTransferEncoding(value) =>
-                    transfer_encoding.set_once(location, "Content-Transfer-Encoding", value)?,
+                    transfer_encoding.set_once(errors, location, "Content-Transfer-Encoding", value)?,
                 mime::Header::ContentId(value) =>
-                    content_id.set_once(location, "Content-ID", value)?,
+                    content_id.set_once(errors, location, "Content-ID", value)?,
                 mime::Header::ContentDescription(value) =>
-                    content_description.set_once(location, "Content-Description", value)?,
+                    content_description.set_once(errors, location, "Content-Description", value)?,
+                mime::Header::ContentDisposition(value) =>
+                    content_disposition.set_once(errors, location, "Content-Disposition", value)?,
             },
             Header::Optional { name, body } => {
                 log::trace!("unrecognized header {name}: {body:?}");
@@ -151,38 +216,58 @@ pub fn parse<'a>(message: &'a [u8], errors: &mut Errors) -> Result<ParsedMessage
         }
     }
 
-    let origination_date = origination_date
-        .ok_or_else(|| Located::new(Location::ZERO, "missing required header Origination-Date"))?;
-    let from = from
-        .ok_or_else(|| Located::new(Location::ZERO, "missing required header From"))?;
+    if origination_date.is_none() {
+        errors.recover(Located::new(Location::ZERO, "missing required header Origination-Date"))?;
+    }
+    if from.is_none() {
+        errors.recover(Located::new(Location::ZERO, "missing required header From"))?;
+    }
 
     let body = match mime_version {
         None => Body::Unknown(body.item),
         Some(version) => Body::Mime(mime::Unparsed {
+            header: header_data,
+            id: content_id,
+            description: content_description,
+            disposition: content_disposition,
             data: body,
             version,
-            content_type: content_type.unwrap_or_default(),
+            content_type: content_type
+                .unwrap_or_else(|| Located::new(Location::ZERO, mime::syntax::ContentType::default())),
             transfer_encoding,
         }),
     };
 
     Ok(ParsedMessage {
+        headers,
         trace,
         id,
         origination_date,
-        from,
+        from: from.unwrap_or_default(),
         sender,
+        reply_to: reply_to.unwrap_or_default(),
         to: to.unwrap_or_default(),
+        cc: cc.unwrap_or_default(),
+        bcc: bcc.unwrap_or_default(),
+        in_reply_to: in_reply_to.unwrap_or_default(),
+        references: references.unwrap_or_default(),
         subject,
+        comments,
+        keywords,
         body,
     })
 }
 
 /// Separate message into its header and body sections
 pub fn separate_message(message: &[u8]) -> (&[u8], Located<&[u8]>) {
-    let (header_end, body_start) = match memmem::find(message, b"\r\n\r\n") {
-        Some(cr) => (cr + 2, cr + 4),
-        None => (message.len(), message.len()),
+    let (header_end, body_start) = if message.starts_with(b"\r\n") {
+        // Header section is empty, which is common in MIME body parts.
+        (0, 2)
+    } else {
+        match memmem::find(message, b"\r\n\r\n") {
+            Some(cr) => (cr + 2, cr + 4),
+            None => (message.len(), message.len()),
+        }
     };
 
     let header = &message[..header_end];
@@ -193,31 +278,89 @@ pub fn separate_message(message: &[u8]) -> (&[u8], Located<&[u8]>) {
     (header, Located::new(location, body))
 }
 
-fn parse_trace<'a>(header: &mut Buffer<'a>) -> Result<Option<Trace<'a>>> {
-    // Trace fields
-    let return_path = header.maybe(syntax::return_path);
-    let received = header.list_of::<Received>(if return_path.is_some() { 1 } else { 0 }, usize::MAX, b"")?;
+/// Skip over a header field which couldn't be parsed, up to the next line not
+/// beginning with whitespace
+pub fn skip_field(header: &mut Buffer) {
+    let mut length = 0;
 
-    if return_path.is_none() && received.is_empty() {
-        return Ok(None);
+    while let Some(end) = memmem::find(&header[length..], b"\r\n") {
+        length += end + 2;
+
+        if !header[length..].starts_with(b" ") && !header[length..].starts_with(b"\t") {
+            header.advance(length);
+            return;
+        }
+    }
+
+    header.advance(header.len());
+}
+
+/// List all fields in a message header, without interpreting them
+///
+/// Unlike [`parse`] this never fails; fields which are malformed beyond
+/// recognition are skipped.
+pub fn header_fields(header: &[u8]) -> Vec<HeaderField> {
+    let mut fields = Vec::new();
+    let mut rest = header;
+
+    while !rest.is_empty() {
+        // Field ends with a CRLF not followed by white space.
+        let mut end = 0;
+        let end = loop {
+            match memmem::find(&rest[end..], b"\r\n") {
+                Some(inx) => {
+                    end += inx + 2;
+                    if !rest[end..].first().is_some_and(|&c| is_wsp(c)) {
+                        break end;
+                    }
+                }
+                None => break rest.len(),
+            }
+        };
+
+        let (field, tail) = rest.split_at(end);
+        rest = tail;
+
+        let field = String::from_utf8_lossy(field.strip_suffix(b"\r\n").unwrap_or(field));
+        if let Some((name, value)) = field.split_once(':') {
+            fields.push(HeaderField {
+                name: name.trim_end().to_string(),
+                value: Folded(value).unfold(),
+            });
+        }
     }
 
+    fields
+}
+
+fn parse_trace<'a>(header: &mut Buffer<'a>, errors: &mut Errors) -> Result<Option<Trace<'a>>> {
+    // Trace fields
+    let return_path = header.maybe(syntax::return_path);
+    let received = header.list_of::<Received>(0, usize::MAX, b"")?;
+
     // Optional fields
-    let mut cursor = *header;
-    while let Some(Header::Optional { .. }) = cursor.maybe(syntax::field) {
-        *header = cursor;
+    if return_path.is_some() || !received.is_empty() {
+        let mut cursor = *header;
+        while let Some(Header::Optional { .. }) = cursor.maybe(syntax::field) {
+            *header = cursor;
+        }
     }
 
     // Resending data
     let mut resending = vec![];
-    while let Some(info) = parse_resent_block(header)? {
+    while let Some(info) = parse_resent_block(header, errors)? {
         resending.push(info);
     }
 
+    if return_path.is_none() && received.is_empty() && resending.is_empty() {
+        return Ok(None);
+    }
+
     Ok(Some(Trace { return_path, received, resending }))
 }
 
-fn parse_resent_block<'a>(header: &mut Buffer<'a>) -> Result<Option<ResentInfo<'a>>> {
+fn parse_resent_block<'a>(header: &mut Buffer<'a>, errors: &mut Errors)
+-> Result<Option<ResentInfo<'a>>> {
     let location = header.location();
 
     let mut date = None;
@@ -230,8 +373,13 @@ fn parse_resent_block<'a>(header: &mut Buffer<'a>) -> Result<Option<ResentInfo<'
 
     let mut cursor = *header;
 
-    while !header.is_empty() {
-        match syntax::field(&mut cursor)? {
+    while !cursor.is_empty() {
+        let field = match syntax::field(&mut cursor) {
+            Ok(field) => field,
+            Err(_) => break,
+        };
+
+        match field {
             Header::ResentDate(value) => {
                 if date.is_some() {
                     break;
@@ -285,14 +433,17 @@ fn parse_resent_block<'a>(header: &mut Buffer<'a>) -> Result<Option<ResentInfo<'
         return Ok(None);
     }
 
-    let date = date
-        .ok_or_else(|| Located::new(location, "missing required header Resent-Date"))?;
-    let from = from
-        .ok_or_else(|| Located::new(location, "missing required header Resent-From"))?;
+    if date.is_none() {
+        errors.recover(Located::new(location, "missing required header Resent-Date"))?;
+    }
+
+    if from.is_none() {
+        errors.recover(Located::new(location, "missing required header Resent-From"))?;
+    }
 
     Ok(Some(ResentInfo {
         date,
-        from,
+        from: from.unwrap_or_default(),
         sender,
         to: to.unwrap_or_default(),
         cc: cc.unwrap_or_default(),
@@ -2,7 +2,7 @@
 // Licensed under the MIT license. See LICENSE file in the project root for
 // full license text.
 
-use std::{str, borrow::Cow};
+use std::{fmt, slice, str, borrow::Cow};
 use serde::Serialize;
 use time::{Weekday, Month, UtcOffset, Time, Date, OffsetDateTime, PrimitiveDateTime};
 
@@ -35,8 +35,9 @@ pub fn comment(buf: &mut Buffer) -> Result<()> {
         while !buf.is_empty() && !buf.starts_with(b")") {
             // ccontent = ctext / quoted-pair / comment
             match buf[0] {
-                // ctext = %d33-39 / %d42-91 / %d93-126 / obs-ctext
+                // ctext = %d33-39 / %d42-91 / %d93-126 / obs-ctext / UTF8-non-ascii
                 33..=39 | 42..=91 | 93..=126 => buf.advance(1),
+                0x80.. => utf8_non_ascii(buf)?,
                 // quoted-pair = ("\" (VCHAR / WSP)) / obs-qp
                 b'\\' if buf.len() >= 2 => match buf[1] {
                     c if is_vchar(c) || is_wsp(c) => buf.advance(2),
@@ -98,26 +99,25 @@ pub struct Quoted<'a>(pub &'a str);
 
 impl<'a> Quoted<'a> {
     pub fn unquote(&self) -> Cow<'a, str> {
-        let mut result = String::new();
-        let mut rest = self.0;
+        if !self.0.contains(&['\r', '\\']) {
+            return Cow::from(self.0);
+        }
 
-        while let Some(inx) = rest.find(&['\r', '\\']) {
-            if inx > 0 {
-                result.push_str(&rest[..inx]);
-            }
+        let mut result = String::with_capacity(self.0.len());
+        let mut chars = self.0.chars();
 
-            if rest[inx..].starts_with("\r\n") {
-                rest = &rest[2..];
-            } else /* starts with \ */ {
-                rest = &rest[1..];
+        while let Some(c) = chars.next() {
+            match c {
+                // CR can only appear as part of a folding CRLF.
+                '\r' => {
+                    chars.next();
+                }
+                '\\' => result.extend(chars.next()),
+                c => result.push(c),
             }
         }
 
-        if result.is_empty() {
-            Cow::from(self.0)
-        } else {
-            Cow::from(result)
-        }
+        Cow::from(result)
     }
 }
 
@@ -130,19 +130,22 @@ pub fn quoted_string<'a>(buf: &mut Buffer<'a>) -> Result<Quoted<'a>> {
         buf.expect(b"\"")?;
 
         // qcontent    = qtext / quoted-pair
-        // qtext       = %d33 / %d35-91 / %d93-126 / obs-qtext
+        // qtext       = %d33 / %d35-91 / %d93-126 / obs-qtext / UTF8-non-ascii
         // quoted-pair = ("\" (VCHAR / WSP)) / obs-qp
         let value = buf.take_matching(|buf| {
             buf.maybe(fws);
             while !buf.is_empty() && !buf.starts_with(b"\"") {
                 match buf[0] {
                     33 | 35..=91 | 93..=126 => buf.advance(1),
+                    0x80.. => utf8_non_ascii(buf)?,
                     b'\\' if buf.len() >= 2 => match buf[1] {
                         0x21..=0x7e | b' ' | b'\t' => buf.advance(2),
                         _ => return buf.error("invalid escape sequence"),
                     },
                     _ => return buf.error("illegal character in quoted string"),
                 }
+
+                buf.maybe(fws);
             }
             Ok(())
         })?;
@@ -168,10 +171,21 @@ impl Phrase<'_> {
     pub fn unquote(&self) -> String {
         let mut result = String::new();
         let mut rest = Buffer::new(self.0.as_bytes());
+        let mut after_encoded_word = false;
 
         while !rest.is_empty() {
-            let word = word(&mut rest).expect("invalid pre-parsed string");
-            result.push_str(&decode_word(&word.unquote()));
+            let word = word(&mut rest).expect("invalid pre-parsed string").unquote();
+            let decoded = decode_word(&word);
+            // Space between adjacent encoded words is dropped (RFC 2047
+            // section 6.2), and decode_word only allocates when it decodes.
+            let encoded = matches!(decoded, Cow::Owned(_));
+
+            if !result.is_empty() && (!encoded || !after_encoded_word) {
+                result.push(' ');
+            }
+
+            result.push_str(&decoded);
+            after_encoded_word = encoded;
         }
 
         result
@@ -203,32 +217,56 @@ fn phrase<'a>(buf: &mut Buffer<'a>) -> Result<Phrase<'a>> {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub struct Folded<'a>(&'a str);
+pub struct Folded<'a>(pub &'a str);
 
 impl<'a> Folded<'a> {
     pub fn unfold(&self) -> String {
         let mut result = String::new();
         let mut buf = Buffer::new(self.0.as_bytes());
+        // White space is only added once we know what follows it, as it is
+        // dropped at the beginning and end of the value, and between adjacent
+        // encoded words (RFC 2047 section 6.2).
+        let mut space = String::new();
+        let mut after_encoded_word = false;
 
         while !buf.is_empty() {
-            buf.maybe(|buf| {
-                let word = encoded_word(buf)?
-                    .decode()
-                    .map_err(|_| Located::new(buf.location(), ""))?;
-                result.push_str(&word);
-                Ok(())
-            });
+            // Unfolding removes CRLF preceding white space.
+            if buf.starts_with(b"\r\n") {
+                buf.advance(2);
+                continue;
+            }
 
             let fragment = buf.take_while(|b, _| is_wsp(b));
             if !fragment.is_empty() {
-                result.push_str(str::from_utf8(fragment).unwrap());
+                space.push_str(str::from_utf8(fragment).unwrap());
                 continue;
             }
 
-            let fragment = buf.take_while(|b, _| is_vchar(b) && b != b' ');
-            result.push_str(str::from_utf8(fragment).unwrap());
+            let word = buf.maybe(|buf| encoded_word(buf)?
+                .decode()
+                .map_err(|_| Located::new(buf.location(), "")));
 
-            buf.maybe(fws);
+            if let Some(word) = word {
+                if !after_encoded_word && !result.is_empty() {
+                    result.push_str(&space);
+                }
+                result.push_str(&word);
+                space.clear();
+                after_encoded_word = true;
+                continue;
+            }
+
+            let mut fragment = buf.take_while(|b, _| !is_wsp(b) && b != b'\r');
+            if fragment.is_empty() {
+                fragment = buf.take(1);
+            }
+
+            if !result.is_empty() {
+                result.push_str(&space);
+            }
+            result.push_str(str::from_utf8(fragment).unwrap());
+            space.clear();
+            after_encoded_word = false;
         }
 
         result
@@ -237,12 +275,13 @@ impl<'a> Folded<'a> {
 
 pub fn unstructured<'a>(buf: &mut Buffer<'a>) -> Result<Folded<'a>> {
     // unstructured = (*([FWS] VCHAR) *WSP) / obs-unstruct
+    // VCHAR        =/ UTF8-non-ascii
 
     let value = buf.take_matching(|buf| {
         while !buf.is_empty() {
             buf.maybe(fws);
 
-            if buf.take_while(|b, _| is_vchar(b)).is_empty() {
+            if buf.take_while_utf8(is_vchar).is_empty() {
                 break;
             }
         }
@@ -509,6 +548,16 @@ impl<'a> Parse<'a> for AddressOrGroupRef<'a> {
     }
 }
 
+impl AddressOrGroup {
+    /// All mailboxes in this address
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        match self {
+            AddressOrGroup::Mailbox(mailbox) => slice::from_ref(mailbox),
+            AddressOrGroup::Group(group) => &group.members,
+        }
+    }
+}
+
 impl AddressOrGroupRef<'_> {
     pub fn to_owned(self) -> AddressOrGroup {
         match self {
@@ -657,6 +706,12 @@ pub struct Address {
     pub domain: String,
 }
 
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.local, self.domain)
+    }
+}
+
 impl AddressRef<'_> {
     pub fn to_owned(self) -> Address {
         Address {
@@ -694,6 +749,7 @@ pub fn is_dtext(c: u8) -> bool {
 
 pub fn domain_literal<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
     // domain-literal = [CFWS] "[" *([FWS] dtext) [FWS] "]" [CFWS]
+    // dtext          =/ UTF8-non-ascii
     buf.atomic(|buf| {
         buf.maybe(cfws);
         buf.expect(b"[")?;
@@ -704,6 +760,7 @@ pub fn domain_literal<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
         while !cursor.is_empty() && !cursor.starts_with(b"]") {
             match cursor[0] {
                 c if is_dtext(c) => cursor.advance(1),
+                0x80.. => utf8_non_ascii(&mut cursor)?,
                 _ => return buf.error("expected text"),
             }
         }
@@ -803,7 +860,7 @@ pub fn field<'a>(buf: &mut Buffer<'a>) -> Result<Header<'a>> {
             Header::From(mailbox_list(buf)?)
         } else if name.eq_ignore_ascii_case("Sender") {
             Header::Sender(mailbox(buf)?)
-        } else if name.eq_ignore_ascii_case("Reply-To:") {
+        } else if name.eq_ignore_ascii_case("Reply-To") {
             Header::ReplyTo(address_list(buf)?)
         } else if name.eq_ignore_ascii_case("To") {
             Header::To(address_list(buf)?)
@@ -912,9 +969,10 @@ pub fn msg_id<'a>(buf: &mut Buffer<'a>) -> Result<MessageIdRef<'a>> {
 
 fn no_fold_literal<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
     // no-fold-literal = "[" *dtext "]"
+    // dtext           =/ UTF8-non-ascii
     buf.atomic(|buf| {
         buf.expect(b"[")?;
-        let value = buf.take_while(|c, _| is_dtext(c));
+        let value = buf.take_while_utf8(is_dtext);
         buf.expect(b"]")?;
         Ok(str::from_utf8(value).unwrap())
     })
@@ -941,10 +999,34 @@ pub enum PathRef<'a> {
     Address(AddressRef<'a>),
 }
 
+#[derive(Clone, Debug)]
+pub enum Path {
+    Null,
+    Address(Address),
+}
+
+impl PathRef<'_> {
+    pub fn to_owned(self) -> Path {
+        match self {
+            PathRef::Null => Path::Null,
+            PathRef::Address(address) => Path::Address(address.to_owned()),
+        }
+    }
+}
+
+impl Serialize for Path {
+    fn serialize<S: serde::Serializer>(&self, se: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Path::Null => se.serialize_none(),
+            Path::Address(address) => address.serialize(se),
+        }
+    }
+}
+
 pub fn return_path<'a>(buf: &mut Buffer<'a>) -> Result<PathRef<'a>> {
     // return = "Return-Path:" path CRLF
     buf.atomic(|buf| {
-        buf.expect(b"Return-Path:")?;
+        buf.expect_caseless(b"Return-Path:")?;
         let path = path(buf)?;
         buf.expect(b"\r\n")?;
         Ok(path)
@@ -965,6 +1047,8 @@ fn path<'a>(buf: &mut Buffer<'a>) -> Result<PathRef<'a>> {
 #[derive(Clone, Copy, Debug)]
 pub struct Received<'a> {
     pub tokens: ListOf<'a, ReceivedToken<'a>>,
+    /// Text of all tokens, including comments
+    pub text: Folded<'a>,
     pub date: AnyDateTime,
 }
 
@@ -977,7 +1061,7 @@ impl<'a> Parse<'a> for Received<'a> {
 pub fn received<'a>(buf: &mut Buffer<'a>) -> Result<Received<'a>> {
     // received = "Received:" *received-token ";" date-time CRLF
     buf.atomic(|buf| {
-        buf.expect(b"Received:")?;
+        buf.expect_caseless(b"Received:")?;
         let value = received_value(buf)?;
         buf.expect(b"\r\n")?;
         Ok(value)
@@ -988,10 +1072,14 @@ fn received_value<'a>(buf: &mut Buffer<'a>) -> Result<Received<'a>> {
     // received       = *received-token ";" date-time
     // received-token = word / angle-addr / addr-spec / domain
     buf.atomic(|buf| {
-        let tokens = buf.list_of(0, usize::MAX, b"")?;
+        let mut tokens = ListOf::empty();
+        let text = buf.take_matching(|buf| {
+            tokens = buf.list_of(0, usize::MAX, b"")?;
+            Ok(())
+        })?;
         buf.expect(b";")?;
         let date = date_time(buf)?;
-        Ok(Received { tokens, date })
+        Ok(Received { tokens, text: Folded(str::from_utf8(text).unwrap()), date })
     })
 }
 
@@ -1013,3 +1101,22 @@ fn received_token<'a>(buf: &mut Buffer<'a>) -> Result<ReceivedToken<'a>> {
         .or_else(|_| addr_spec(buf).map(ReceivedToken::Address))
         .or_else(|_| domain(buf).map(ReceivedToken::Domain))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unfold() {
+        let unfold = |value| Folded(value).unfold();
+
+        assert_eq!(unfold("This is a\r\n test"), "This is a test");
+        assert_eq!(unfold(" leading and\r\n\ttrailing \r\n "), "leading and\ttrailing");
+        // Examples from RFC 2047 section 8
+        assert_eq!(unfold("=?ISO-8859-1?Q?a?= b"), "a b");
+        assert_eq!(unfold("=?ISO-8859-1?Q?a?= =?ISO-8859-1?Q?b?="), "ab");
+        assert_eq!(unfold("=?ISO-8859-1?Q?a?=\r\n   =?ISO-8859-1?Q?b?="), "ab");
+        assert_eq!(unfold("=?ISO-8859-1?Q?a_b?="), "a b");
+        assert_eq!(unfold("a =?UTF-8?B?xbzDs8WCdw==?= b"), "a żółw b");
+    }
+}
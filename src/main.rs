@@ -13,6 +13,7 @@ mod mail;
 mod mime;
 mod smtp;
 mod state;
+mod storage;
 mod syntax;
 mod util;
 mod web;
@@ -27,7 +28,9 @@ async fn main() -> Result<()> {
     let config = config::load()?;
     log::trace!("config = {config:#?}");
 
-    let state = state::State::new();
+    let storage = storage::Storage::open(&config.storage).await?;
+    let state = state::State::new(storage);
+    state.load().await?;
 
     let smtp = try_spawn(smtp::server::start(config.smtp, state.clone()));
     let web = web::start(config.http, state);
@@ -2,12 +2,12 @@
 // Licensed under the MIT license. See LICENSE file in the project root for
 // full license text.
 
-use std::{error::Error, fmt, borrow::Cow};
+use std::{error::Error, fmt, borrow::Cow, collections::BTreeMap};
 use thiserror::Error;
 
 use crate::{util, syntax::Buffer};
 
-use super::syntax::{TransferEncoding, encoded_word};
+use super::syntax::{TransferEncoding, Parameter, encoded_word};
 
 impl TransferEncoding {
     pub fn decode(self, data: &[u8]) -> Result<Cow<[u8]>, DecodeError> {
@@ -58,45 +58,48 @@ mod quoted_printable {
     use super::{DecodeError, DecodeErrorKind};
 
     pub fn decode(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
-        let data = std::str::from_utf8(data).expect("TODO");
         let mut result = Vec::with_capacity(data.len());
 
-        for mut line in data.split_inclusive("\r\n") {
+        // Data is not necessarily UTF-8, as raw 8-bit octets may appear in
+        // a message sent with BODY=8BITMIME, BINARYMIME, or SMTPUTF8, even
+        // though they are not allowed in quoted-printable.
+        for mut line in data.split_inclusive(|&b| b == b'\n') {
             if line.len() > 80 /* 78 + \r\n */ {
                 return Err(DecodeErrorKind::LineOverflow.into());
             }
 
             while !line.is_empty() {
-                if line == "=\r\n" {
+                if line == b"=\r\n" {
                     break;
                 }
 
-                if line.starts_with('=') {
-                    let h = line.as_bytes()[1];
-                    let l = line.as_bytes()[2];
+                if line.starts_with(b"=") {
+                    // Data need not end with a CRLF (for example when it was
+                    // sent using BDAT), so an escape may be cut short.
+                    let hex = match line.get(1..3) {
+                        Some(hex) if hex.iter().all(|&b| matches!(b, b'0'..=b'9' | b'A'..=b'F'))
+                            => hex,
+                        _ => return Err(DecodeErrorKind::InvalidEscapeSequence.into()),
+                    };
 
-                    if !matches!(h, b'0'..=b'9' | b'A'..=b'F')
-                    || !matches!(l, b'0'..=b'9' | b'A'..=b'F') {
-                        return Err(DecodeErrorKind::InvalidEscapeSequence.into());
-                    }
-
-                    let byte = u8::from_str_radix(&line[1..3], 16).unwrap();
+                    let byte = u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap();
                     result.push(byte);
 
                     line = &line[3..];
                 } else {
-                    let next = line.find('=').unwrap_or(line.len());
+                    let next = line.iter().position(|&b| b == b'=').unwrap_or(line.len());
                     let fragment = &line[..next];
                     line = &line[next..];
 
-                    if fragment.trim_end_matches("\r\n")
-                        .bytes()
-                        .any(|b| b.is_ascii_control() && b != b'\t' || b > 126)
+                    if fragment.strip_suffix(b"\r\n")
+                        .unwrap_or(fragment)
+                        .iter()
+                        .any(|&b| b.is_ascii_control() && b != b'\t' || b > 126)
                     {
                         return Err(DecodeErrorKind::IllegalCharacter.into());
                     }
 
-                    result.extend_from_slice(fragment.as_bytes());
+                    result.extend_from_slice(fragment);
                 }
             }
         }
@@ -150,19 +153,14 @@ impl Error for DecodeError {
 #[derive(Clone, Copy)]
 pub enum Charset {
     UsAscii,
-    Iso8859_2,
-    Iso8859_3,
-    Iso8859_4,
-    Iso8859_5,
-    Iso8859_6,
-    Iso8859_7,
-    Iso8859_8,
-    Iso8859_10,
-    Iso8859_13,
-    Iso8859_14,
-    Iso8859_15,
-    Iso8859_16,
     Utf8,
+    /// UTF-16 with byte order given by a byte order mark, big-endian if there
+    /// is none (RFC 2781)
+    Utf16,
+    /// UTF-7 (RFC 2152)
+    Utf7,
+    /// Any other character set supported by `encoding_rs`
+    Other(&'static encoding_rs::Encoding),
 }
 
 #[derive(Debug, Error)]
@@ -170,30 +168,28 @@ pub enum Charset {
 pub struct CharsetError;
 
 impl Charset {
+    /// Find a character set by its name or any of its aliases
+    ///
+    /// Names are those of the IANA registry and the WHATWG Encoding Standard.
     pub fn by_name(name: &str) -> Option<Charset> {
         Some(match_ignore_ascii_case! { name;
-            "US-ASCII" => Charset::UsAscii,
-            "ISO-8859-2" => Charset::Iso8859_2,
-            "ISO-8859-3" => Charset::Iso8859_3,
-            "ISO-8859-4" => Charset::Iso8859_4,
-            "ISO-8859-5" => Charset::Iso8859_5,
-            "ISO-8859-6" => Charset::Iso8859_6,
-            "ISO-8859-7" => Charset::Iso8859_7,
-            "ISO-8859-8" => Charset::Iso8859_8,
-            "ISO-8859-10" => Charset::Iso8859_10,
-            "ISO-8859-13" => Charset::Iso8859_13,
-            "ISO-8859-14" => Charset::Iso8859_14,
-            "ISO-8859-15" => Charset::Iso8859_15,
-            "ISO-8859-16" => Charset::Iso8859_16,
-            "UTF-8" => Charset::Utf8,
-            _ => return None,
+            "US-ASCII" | "ASCII" | "ANSI_X3.4-1968" => Charset::UsAscii,
+            "UTF-16" => Charset::Utf16,
+            "UTF-7" | "UNICODE-1-1-UTF-7" => Charset::Utf7,
+            _ => match encoding_rs::Encoding::for_label(name.as_bytes())? {
+                encoding if encoding == encoding_rs::UTF_8 => Charset::Utf8,
+                // The replacement encoding exists only to prevent decoding
+                // of some unsafe character sets.
+                encoding if encoding == encoding_rs::REPLACEMENT => return None,
+                encoding => Charset::Other(encoding),
+            },
         })
     }
 
     pub fn decode(self, data: &[u8]) -> Result<Cow<str>, CharsetError> {
         use encoding_rs::*;
 
-        let charset = match self {
+        let (charset, data) = match self {
             Charset::UsAscii => {
                 return if data.iter().all(u8::is_ascii) {
                     Ok(std::str::from_utf8(data).unwrap().into())
@@ -201,26 +197,90 @@ impl Charset {
                     Err(CharsetError)
                 };
             }
-            Charset::Iso8859_2 => ISO_8859_2,
-            Charset::Iso8859_3 => ISO_8859_3,
-            Charset::Iso8859_4 => ISO_8859_4,
-            Charset::Iso8859_5 => ISO_8859_5,
-            Charset::Iso8859_6 => ISO_8859_6,
-            Charset::Iso8859_7 => ISO_8859_7,
-            Charset::Iso8859_8 => ISO_8859_8,
-            Charset::Iso8859_10 => ISO_8859_10,
-            Charset::Iso8859_13 => ISO_8859_13,
-            Charset::Iso8859_14 => ISO_8859_14,
-            Charset::Iso8859_15 => ISO_8859_15,
-            Charset::Iso8859_16 => ISO_8859_16,
             Charset::Utf8 =>
                 return std::str::from_utf8(data).map(Cow::from).map_err(|_| CharsetError),
+            Charset::Utf16 => match data {
+                [0xff, 0xfe, rest @ ..] => (UTF_16LE, rest),
+                [0xfe, 0xff, rest @ ..] => (UTF_16BE, rest),
+                _ => (UTF_16BE, data),
+            },
+            Charset::Utf7 => return utf7::decode(data).map(Cow::from),
+            Charset::Other(charset) => (charset, data),
         };
 
         charset.decode_without_bom_handling_and_without_replacement(data).ok_or(CharsetError)
     }
 }
 
+mod utf7 {
+    use super::CharsetError;
+
+    /// Decode UTF-7 (RFC 2152)
+    pub fn decode(data: &[u8]) -> Result<String, CharsetError> {
+        let mut result = String::with_capacity(data.len());
+        let mut rest = data;
+
+        while let Some((&byte, tail)) = rest.split_first() {
+            rest = tail;
+
+            if !byte.is_ascii() {
+                return Err(CharsetError);
+            }
+
+            if byte != b'+' {
+                result.push(char::from(byte));
+                continue;
+            }
+
+            // "+-" is an encoded "+".
+            if let Some(tail) = rest.strip_prefix(b"-") {
+                result.push('+');
+                rest = tail;
+                continue;
+            }
+
+            // Modified base64 sequence of UTF-16 code units, ending at the
+            // first character not in the base64 alphabet; a "-" ending it is
+            // absorbed.
+            let length = rest.iter().position(|&b| sextet(b).is_none()).unwrap_or(rest.len());
+            let (encoded, tail) = rest.split_at(length);
+            rest = tail.strip_prefix(b"-").unwrap_or(tail);
+
+            let mut units = Vec::with_capacity(encoded.len() * 6 / 16);
+            let mut bits = 0u32;
+            let mut count = 0;
+
+            for &b in encoded {
+                bits = bits << 6 | u32::from(sextet(b).unwrap());
+                count += 6;
+
+                if count >= 16 {
+                    count -= 16;
+                    units.push((bits >> count) as u16);
+                    bits &= (1 << count) - 1;
+                }
+            }
+
+            for c in char::decode_utf16(units) {
+                result.push(c.map_err(|_| CharsetError)?);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn sextet(b: u8) -> Option<u8> {
+        Some(match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        })
+    }
+}
+
 pub fn decode_word(word: &str) -> Cow<str> {
     let mut buf = Buffer::new(word.as_bytes());
     match encoded_word(&mut buf) {
@@ -232,6 +292,147 @@ pub fn decode_word(word: &str) -> Cow<str> {
     }
 }
 
+/// Decode parameter values, joining RFC 2231 continuations and decoding
+/// charset-encoded values
+///
+/// Returns lower-cased attribute names, without RFC 2231 section markers,
+/// together with their values, in order of first occurrence.
+pub fn decode_parameters<'a>(parameters: impl Iterator<Item = Parameter<'a>>)
+-> Vec<(String, String)> {
+    #[derive(Default)]
+    struct Value<'a> {
+        plain: Option<Cow<'a, str>>,
+        /// Sections of an RFC 2231 value, and whether each one is encoded
+        sections: BTreeMap<u32, (bool, Cow<'a, str>)>,
+    }
+
+    let mut values: Vec<(String, Value)> = Vec::new();
+
+    for param in parameters {
+        let attribute = param.attribute.to_ascii_lowercase();
+        let (name, section, encoded) = split_attribute(&attribute);
+
+        let index = match values.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                values.push((name.to_string(), Value::default()));
+                values.len() - 1
+            }
+        };
+        let value = &mut values[index].1;
+
+        match (section, encoded) {
+            (None, false) => value.plain = Some(param.value.unquote()),
+            (None, true) => {
+                value.sections.insert(0, (true, param.value.unquote()));
+            }
+            (Some(section), _) => {
+                value.sections.insert(section, (encoded, param.value.unquote()));
+            }
+        }
+    }
+
+    values.into_iter()
+        .map(|(name, value)| {
+            let value = if value.sections.is_empty() {
+                value.plain.map(Cow::into_owned).unwrap_or_default()
+            } else {
+                join_sections(value.sections)
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+/// Split an RFC 2231 attribute into its name, section number, and whether its
+/// value is encoded
+///
+/// Attributes with a malformed section number are returned verbatim.
+fn split_attribute(attribute: &str) -> (&str, Option<u32>, bool) {
+    let (name, encoded) = match attribute.strip_suffix('*') {
+        Some(name) => (name, true),
+        None => (attribute, false),
+    };
+
+    let (name, section) = match name.rsplit_once('*') {
+        Some(split) => split,
+        None => return (name, None, encoded),
+    };
+
+    // section := "0" / (("1" / "2" / "3" / "4" / "5" / "6" / "7" / "8" / "9") *DIGIT)
+    let valid = !section.is_empty()
+        && section.bytes().all(|b| b.is_ascii_digit())
+        && (section == "0" || !section.starts_with('0'));
+
+    match section.parse() {
+        Ok(section) if valid => (name, Some(section), encoded),
+        _ => (attribute, None, false),
+    }
+}
+
+/// Encode a parameter value as an RFC 2231 extended value, in UTF-8
+pub fn encode_extended_value(value: &str) -> String {
+    let mut result = String::from("UTF-8''");
+
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+            | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~'
+                => result.push(char::from(byte)),
+            _ => result.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    result
+}
+
+/// Join sections of an RFC 2231 parameter value
+fn join_sections(sections: BTreeMap<u32, (bool, Cow<str>)>) -> String {
+    let mut charset = None;
+    let mut data = Vec::new();
+
+    for (section, (encoded, value)) in sections {
+        if !encoded {
+            data.extend_from_slice(value.as_bytes());
+            continue;
+        }
+
+        let mut value = &*value;
+
+        // extended-initial-value := [charset] "'" [language] "'"
+        //                           extended-other-values
+        if section == 0 {
+            let mut parts = value.splitn(3, '\'');
+            if let (Some(name), Some(_language), Some(rest)) = (parts.next(), parts.next(), parts.next()) {
+                charset = Charset::by_name(name);
+                value = rest;
+            }
+        }
+
+        let mut bytes = value.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'%' {
+                data.push(byte);
+                continue;
+            }
+
+            let hex = bytes.clone().take(2).collect::<Vec<_>>();
+            match std::str::from_utf8(&hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) if hex.len() == 2 => {
+                    data.push(byte);
+                    bytes.nth(1);
+                }
+                _ => data.push(byte),
+            }
+        }
+    }
+
+    match charset.and_then(|charset| charset.decode(&data).ok()) {
+        Some(value) => value.into_owned(),
+        None => String::from_utf8_lossy(&data).into_owned(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,5 +445,60 @@ mod tests {
             ).unwrap(),
             (b"Now's the time for all folk to come to the aid of their country."),
         );
+        assert!(quoted_printable::decode(b"\xbf\xf3\xb3w=3D\r\n").is_err());
+        assert_eq!(quoted_printable::decode(b"a=3D").unwrap(), b"a=");
+        assert!(quoted_printable::decode(b"a=4").is_err());
+        assert!(quoted_printable::decode(b"a=").is_err());
+    }
+
+    #[test]
+    fn charsets() {
+        let decode = |name, data| Charset::by_name(name).unwrap().decode(data).unwrap().into_owned();
+
+        assert_eq!(decode("windows-1250", b"\xbf\xf3\xb3w"), "żółw");
+        assert_eq!(decode("KOI8-R", b"\xcd\xc9\xd2"), "мир");
+        assert_eq!(decode("Shift_JIS", b"\x93\xfa\x96\x7b"), "日本");
+        assert_eq!(decode("UTF-16", b"\xff\xfe\x7c\x01\x42\x01"), "żł");
+        assert_eq!(decode("UTF-16", b"\x01\x7c\x01\x42"), "żł");
+        assert_eq!(decode("UTF-7", b"Hi Mom -+Jjo--! 1 +- 1"), "Hi Mom -☺-! 1 + 1");
+        assert_eq!(decode("utf-7", b"+ZeVnLIqe-"), "日本語");
+        assert!(Charset::by_name("x-unknown").is_none());
+    }
+
+    #[test]
+    fn rfc2231_parameters() {
+        let parse = |data: &'static str| {
+            let mut buf = Buffer::new(data.as_bytes());
+            let value = super::super::syntax::content_disposition(&mut buf).unwrap();
+            decode_parameters(value.parameters())
+        };
+
+        assert_eq!(
+            parse("attachment; filename*=utf-8''%C5%BC%C3%B3%C5%82w.txt; size=3"),
+            [("filename".into(), "żółw.txt".into()), ("size".into(), "3".into())],
+        );
+        assert_eq!(
+            parse("inline; Filename*0=\"long \"; filename*1*=%E2%82%AC; filename*2=.txt"),
+            [("filename".into(), "long €.txt".into())],
+        );
+        assert_eq!(
+            parse("attachment; filename=fallback.txt; filename*=iso-8859-2'pl'%B1.txt"),
+            [("filename".into(), "ą.txt".into())],
+        );
+        // Only sections numbered without a sign or leading zeros are joined.
+        assert_eq!(
+            parse("attachment; filename*0=a; filename*+1=b; filename*01=c; filename*1=d"),
+            [
+                ("filename".into(), "ad".into()),
+                ("filename*+1".into(), "b".into()),
+                ("filename*01".into(), "c".into()),
+            ],
+        );
+        assert_eq!(
+            parse("attachment; name=a; name*x=b; name*x*=c"),
+            [("name".into(), "a".into()), ("name*x".into(), "b".into()), ("name*x*".into(), "c".into())],
+        );
+
+        assert_eq!(encode_extended_value("żółw 1.txt"), "UTF-8''%C5%BC%C3%B3%C5%82w%201.txt");
     }
 }
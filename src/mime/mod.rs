@@ -3,15 +3,23 @@
 // full license text.
 
 use axum::http::HeaderValue;
+use serde::Serialize;
 use std::{fmt, borrow::Cow};
 use thiserror::Error;
+use time::{OffsetDateTime, UtcOffset};
 
 mod multipart;
 
 pub mod encoding;
 pub mod syntax;
 
-use crate::{mime::encoding::Charset, util, syntax::{Located, SyntaxError}, state::Errors};
+use crate::{
+    mail::{self, HeaderField},
+    mime::encoding::Charset,
+    state::Errors,
+    syntax::{Buffer, Located, Location, SyntaxError},
+    util,
+};
 
 pub use self::{
     multipart::{Multipart, MultipartKind},
@@ -20,6 +28,16 @@ pub use self::{
 
 #[derive(Debug)]
 pub struct Entity {
+    /// All header fields of this entity, in order in which they appear
+    pub headers: Vec<HeaderField>,
+    /// Value of the Content-ID header, without angle brackets
+    pub id: Option<String>,
+    /// Value of the Content-Description header
+    pub description: Option<String>,
+    /// Suggested file name, from Content-Disposition or the name parameter
+    /// of Content-Type
+    pub filename: Option<String>,
+    pub disposition: Option<Disposition>,
     pub data: EntityData,
     pub content_type: ContentType,
 }
@@ -30,12 +48,36 @@ pub enum EntityData {
     /// Any binary data, such as application/octet-stream, or image/*
     Binary(Vec<u8>),
     Multipart(Multipart),
+    /// message/rfc822 or message/global: another message embedded in this one
+    Message(Box<Entity>),
+}
+
+/// Presentation information from the Content-Disposition header (RFC 2183)
+#[derive(Clone, Debug, Serialize)]
+pub struct Disposition {
+    /// Disposition type, in lower case, such as `inline` or `attachment`
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none", with = "time::serde::timestamp::option")]
+    pub created: Option<OffsetDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "time::serde::timestamp::option")]
+    pub modified: Option<OffsetDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "time::serde::timestamp::option")]
+    pub read: Option<OffsetDateTime>,
+    /// Approximate size of the file, in octets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
 }
 
 pub struct Unparsed<'a> {
+    /// Header section of this entity
+    pub header: &'a [u8],
+    pub id: Option<mail::syntax::MessageIdRef<'a>>,
+    pub description: Option<mail::syntax::Folded<'a>>,
+    pub disposition: Option<syntax::ContentDisposition<'a>>,
     pub data: Located<&'a [u8]>,
     pub version: MimeVersion,
-    pub content_type: syntax::ContentType<'a>,
+    pub content_type: Located<syntax::ContentType<'a>>,
     pub transfer_encoding: Option<TransferEncoding>,
 }
 
@@ -57,62 +99,145 @@ pub enum Error {
 
 impl<'a> Unparsed<'a> {
     pub fn parse(self, errors: &mut Errors) -> Result<Entity, Error> {
+        let headers = mail::header_fields(self.header);
+        let id = self.id.map(|id| id.0.to_string());
+        let description = self.description.map(|description| description.unfold());
+
+        let parameters = self.disposition
+            .map(|disposition| encoding::decode_parameters(disposition.parameters()))
+            .unwrap_or_default();
+        let filename = find_parameter(&parameters, "filename")
+            .map(str::to_string)
+            .or_else(|| find_parameter(
+                &encoding::decode_parameters(self.content_type.item.parameters()), "name")
+                .map(str::to_string));
+        let disposition = self.disposition
+            .map(|disposition| Disposition::new(disposition.type_, &parameters, errors));
+
+        let at = self.data.at;
+        let raw = self.data.item;
+        let (data, content_type) = match self.parse_data(errors) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                // When parsing leniently keep contents as they were.
+                errors.recover(Located { at, item: error }).map_err(|error| error.item)?;
+                (EntityData::Binary(raw.to_vec()), ContentType::APPLICATION_OCTET_STREAM)
+            }
+        };
+
+        Ok(Entity { headers, id, description, filename, disposition, data, content_type })
+    }
+
+    fn parse_data(self, errors: &mut Errors) -> Result<(EntityData, ContentType), Error> {
         let data = match self.transfer_encoding {
             Some(encoding) => encoding.decode(self.data.item)?,
             None => Cow::from(self.data.item),
         };
 
-        let mut errors = errors.nested(self.data.at);
+        let content_type = self.content_type.item;
 
-        match_ignore_ascii_case! { self.content_type.type_;
+        match_ignore_ascii_case! { content_type.type_;
             "text" => {
-                let mut charset = Charset::UsAscii;
-
-                for param in self.content_type.parameters() {
-                    #[allow(clippy::single_match)]
-                    match param.attribute {
-                        "charset" => charset = match Charset::by_name(&param.value.unquote()) {
-                            Some(charset) => charset,
-                            None => return Ok(Entity {
-                                data: EntityData::Binary(data.into_owned()),
-                                content_type: ContentType::APPLICATION_OCTET_STREAM,
-                            }),
-                        },
-                        _ => {}
-                    }
-                }
-
-                match_ignore_ascii_case! { self.content_type.subtype;
-                    "html" => Ok(Entity {
-                        data: EntityData::Text(charset.decode(&data)?.into_owned()),
-                        content_type: self.content_type.into(),
-                    }),
-                    _ => Ok(Entity {
-                        data: EntityData::Text(charset.decode(&data)?.into_owned()),
-                        content_type: ContentType::from(self.content_type).with_subtype("plain"),
-                    }),
+                let parameters = encoding::decode_parameters(content_type.parameters());
+                let charset = match find_parameter(&parameters, "charset") {
+                    None => Charset::UsAscii,
+                    Some(name) => match Charset::by_name(name) {
+                        Some(charset) => charset,
+                        None => return Ok((
+                            EntityData::Binary(data.into_owned()),
+                            ContentType::APPLICATION_OCTET_STREAM,
+                        )),
+                    },
+                };
+
+                match_ignore_ascii_case! { content_type.subtype;
+                    "html" => Ok((
+                        EntityData::Text(charset.decode(&data)?.into_owned()),
+                        content_type.into(),
+                    )),
+                    _ => Ok((
+                        EntityData::Text(charset.decode(&data)?.into_owned()),
+                        ContentType::from(content_type).with_subtype("plain"),
+                    )),
                 }
             }
 
-            "audio" | "image" | "video" => Ok(Entity {
-                data: EntityData::Binary(data.into_owned()),
-                content_type: self.content_type.into(),
-            }),
+            "audio" | "image" | "video" => Ok((
+                EntityData::Binary(data.into_owned()),
+                content_type.into(),
+            )),
 
-            "application" => match_ignore_ascii_case! { self.content_type.subtype;
-                _ => Ok(Entity {
-                    data: EntityData::Binary(data.into_owned()),
-                    content_type: ContentType::APPLICATION_OCTET_STREAM,
-                }),
+            "application" => match_ignore_ascii_case! { content_type.subtype;
+                _ => Ok((
+                    EntityData::Binary(data.into_owned()),
+                    ContentType::APPLICATION_OCTET_STREAM,
+                )),
             },
 
-            "multipart" => multipart::parse(self, &mut errors),
+            // Errors in parameters are located in the header, so errors for
+            // parts are nested by the multipart parser itself.
+            "multipart" => Ok((
+                EntityData::Multipart(multipart::parse(&self, errors)?),
+                content_type.into(),
+            )),
+
+            "message" => match_ignore_ascii_case! { content_type.subtype;
+                "rfc822" | "global" => {
+                    let mut errors = errors.nested(self.data.at);
+                    let message = multipart::parse_part(
+                        &self, &mut errors, &data, false, Default::default())?;
+                    Ok((
+                        EntityData::Message(Box::new(message.parse(&mut errors)?)),
+                        content_type.into(),
+                    ))
+                }
+                // Other message types, such as delivery-status, are textual
+                // reports meant to be read by humans as much as by machines.
+                _ => Ok((
+                    EntityData::Text(Charset::Utf8.decode(&data)?.into_owned()),
+                    content_type.into(),
+                )),
+            },
 
             _ => Err(Error::UnsupportedContentType),
         }
     }
 }
 
+fn find_parameter<'a>(parameters: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    parameters.iter()
+        .find(|(attribute, _)| attribute == name)
+        .map(|(_, value)| value.as_str())
+}
+
+impl Disposition {
+    fn new(type_: &str, parameters: &[(String, String)], errors: &mut Errors) -> Self {
+        let mut date = |name| {
+            let value = find_parameter(parameters, name)?;
+            match mail::syntax::date_time(&mut Buffer::new(value.as_bytes())) {
+                Ok(date) => Some(date.with_offset_when_missing(UtcOffset::UTC)),
+                Err(error) => {
+                    errors.add_at(Location::ZERO,
+                        format!("invalid {name} parameter of Content-Disposition - {}", error.item));
+                    None
+                }
+            }
+        };
+
+        Disposition {
+            type_: type_.to_ascii_lowercase(),
+            created: date("creation-date"),
+            modified: date("modification-date"),
+            read: date("read-date"),
+            size: find_parameter(parameters, "size").and_then(|size| size.parse().ok()),
+        }
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.type_ == "attachment"
+    }
+}
+
 impl fmt::Debug for EntityData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -122,6 +247,8 @@ impl fmt::Debug for EntityData {
                 f.debug_tuple("Binary").field(&util::maybe_ascii(data)).finish(),
             EntityData::Multipart(ref mp) =>
                 f.debug_tuple("Multipart").field(mp).finish(),
+            EntityData::Message(ref message) =>
+                f.debug_tuple("Message").field(message).finish(),
         }
     }
 }
@@ -157,6 +284,25 @@ impl ContentType {
     pub fn with_subtype(self, subtype: impl Into<Cow<'static, str>>) -> Self {
         ContentType { subtype: subtype.into(), ..self }
     }
+
+    /// Set value of a parameter, replacing any existing one with the same
+    /// (case insensitive) name
+    pub fn with_parameter(
+        self,
+        attribute: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let attribute = attribute.into();
+        let mut parameters = self.parameters.into_owned();
+        parameters.retain(|(name, _)| !name.eq_ignore_ascii_case(&attribute));
+        parameters.push((attribute, value.into()));
+        ContentType { parameters: parameters.into(), ..self }
+    }
+
+    /// Check (case insensitive) whether this is `type_/subtype`
+    pub fn is(&self, type_: &str, subtype: &str) -> bool {
+        self.type_.eq_ignore_ascii_case(type_) && self.subtype.eq_ignore_ascii_case(subtype)
+    }
 }
 
 impl From<syntax::ContentType<'_>> for ContentType {
@@ -164,11 +310,9 @@ impl From<syntax::ContentType<'_>> for ContentType {
         ContentType {
             type_: Cow::Owned(ct.type_.into()),
             subtype: Cow::Owned(ct.subtype.into()),
-            parameters: ct.parameters()
-                .map(|param| (
-                    Cow::Owned(param.attribute.into()),
-                    Cow::Owned(param.value.unquote().into_owned()),
-                ))
+            parameters: encoding::decode_parameters(ct.parameters())
+                .into_iter()
+                .map(|(attribute, value)| (Cow::Owned(attribute), Cow::Owned(value)))
                 .collect::<Vec<_>>()
                 .into(),
         }
@@ -186,7 +330,11 @@ impl fmt::Display for ContentType {
         write!(f, "{}/{}", self.type_, self.subtype)?;
 
         for (attribute, value) in self.parameters.iter() {
-            write!(f, "; {attribute}={value:?}")?;
+            if value.is_ascii() {
+                write!(f, "; {attribute}={value:?}")?;
+            } else {
+                write!(f, "; {attribute}*={}", encoding::encode_extended_value(value))?;
+            }
         }
 
         Ok(())
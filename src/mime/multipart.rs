@@ -6,8 +6,12 @@ use memchr::memmem;
 use serde::Serialize;
 use thiserror::Error;
 
-use crate::{mail::{syntax as mail, ParseFieldError, separate_message}, syntax::*, state::Errors};
-use super::{Unparsed, Entity, syntax::Header, EntityData};
+use crate::{
+    mail::{syntax as mail, ParseFieldError, separate_message, skip_field},
+    syntax::*,
+    state::Errors,
+};
+use super::{Unparsed, Entity, encoding::decode_parameters, syntax::{ContentType, Header}};
 
 #[derive(Debug)]
 pub struct Multipart {
@@ -15,11 +19,42 @@ pub struct Multipart {
     pub parts: Vec<Entity>,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
 pub enum MultipartKind {
+    /// Independent parts to be presented in order
+    ///
+    /// This is also used for unrecognized subtypes.
     Mixed,
+    /// Alternative versions of the same content, in increasing order of
+    /// preference
     Alternative,
+    /// Independent parts to be presented simultaneously
+    Parallel,
+    /// Collection of messages, whose parts are message/rfc822 by default
+    Digest,
+    /// Compound object consisting of several inter-related parts (RFC 2387)
+    Related {
+        /// Index of the root part
+        start: usize,
+        /// Content type of the root part
+        #[serde(rename = "type")]
+        type_: Option<String>,
+    },
+    /// Report intended for mail-processing software (RFC 6522)
+    Report {
+        #[serde(rename = "reportType")]
+        report_type: Option<String>,
+    },
+    /// Content followed by its digital signature (RFC 1847)
+    Signed {
+        protocol: Option<String>,
+        micalg: Option<String>,
+    },
+    /// Control information followed by encrypted content (RFC 1847)
+    Encrypted {
+        protocol: Option<String>,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -40,45 +75,79 @@ pub enum Error {
     DuplicateHeader(&'static str),
 }
 
-pub fn parse(from: Unparsed, errors: &mut Errors)
--> Result<Entity, super::Error> {
+pub fn parse(from: &Unparsed, errors: &mut Errors) -> Result<Multipart, super::Error> {
     let mut boundary = None;
+    let mut start = None;
+    let mut type_ = None;
+    let mut report_type = None;
+    let mut protocol = None;
+    let mut micalg = None;
 
-    for param in from.content_type.parameters() {
-        #[allow(clippy::single_match)]
-        match param.attribute {
-            "boundary" => boundary = Some(param.value.unquote()),
-            _ => {}
+    for (attribute, value) in decode_parameters(from.content_type.item.parameters()) {
+        match attribute.as_str() {
+            "boundary" => boundary = Some(value),
+            "start" => start = Some(value),
+            "type" => type_ = Some(value),
+            "report-type" => report_type = Some(value),
+            "protocol" => protocol = Some(value),
+            "micalg" => micalg = Some(value),
+            _ => {},
         }
     }
 
+    let default_type = match_ignore_ascii_case! { from.content_type.item.subtype;
+        "digest" => ContentType::MESSAGE_RFC822,
+        _ => ContentType::default(),
+    };
+
     let boundary = boundary.ok_or(super::Error::MissingRequiredParameter("boundary"))?;
+    let mut data_errors = errors.nested(from.data.at);
     let parts = split(from.data.item, boundary.as_bytes())?
         .map(|part| {
             let Located { at, item: data } = part?;
-            let mut errors = errors.nested(at);
-            let part = parse_part(&from, &mut errors, data, from.transfer_encoding.is_some())?;
+            let mut errors = data_errors.nested(at);
+            let part = parse_part(
+                from, &mut errors, data, from.transfer_encoding.is_some(), default_type)?;
             part.parse(&mut errors)
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let kind = match_ignore_ascii_case! { from.content_type.subtype;
+    let kind = match_ignore_ascii_case! { from.content_type.item.subtype;
         "alternative" => MultipartKind::Alternative,
+        "parallel" => MultipartKind::Parallel,
+        "digest" => MultipartKind::Digest,
+        "related" => MultipartKind::Related {
+            start: match start {
+                // Root part is the first one, unless specified otherwise.
+                None => 0,
+                Some(start) => {
+                    let start = start.trim_start_matches('<').trim_end_matches('>');
+                    match parts.iter().position(|part| part.id.as_deref() == Some(start)) {
+                        Some(index) => index,
+                        None => {
+                            errors.add_at(from.content_type.at,
+                                format!("no part with Content-ID <{start}>"));
+                            0
+                        }
+                    }
+                }
+            },
+            type_,
+        },
+        "report" => MultipartKind::Report { report_type },
+        "signed" => MultipartKind::Signed { protocol, micalg },
+        "encrypted" => MultipartKind::Encrypted { protocol },
         _ => MultipartKind::Mixed,
     };
 
-    Ok(Entity {
-        data: EntityData::Multipart(Multipart { kind, parts }),
-        content_type: from.content_type.into(),
-    })
+    Ok(Multipart { kind, parts })
 }
 
 fn split<'a: 'b, 'b>(data: &'a [u8], boundary: &'b [u8])
 -> Result<impl Iterator<Item = Result<Located<&'a [u8]>, ParseError>> + 'b, ParseError> {
-    let except_last_line = match data.strip_suffix(b"\r\n") {
-        Some(except_last_line) => except_last_line,
-        None => return Err(ParseError::Unterminated),
-    };
+    // Close delimiter of a nested multipart is not followed by a CRLF, as
+    // that belongs to the boundary of the enclosing one.
+    let except_last_line = data.strip_suffix(b"\r\n").unwrap_or(data);
 
     let mut boundaries = memmem::find_iter(except_last_line, b"\r\n")
         .enumerate()
@@ -119,26 +188,35 @@ fn split<'a: 'b, 'b>(data: &'a [u8], boundary: &'b [u8])
         };
 
         (line, start) = next;
-        finished = data[start + 2 + boundary.len()..].starts_with(b"--\r\n");
+        finished = data[start + 2 + boundary.len()..].starts_with(b"--");
+
+        // CRLF preceding a boundary is part of the boundary, not of the body
+        // part (RFC 2046, section 5.1.1).
+        let data_end = (start - 2).max(data_start);
 
-        Some(Ok(Located::new(location, &data[data_start..start])))
+        Some(Ok(Located::new(location, &data[data_start..data_end])))
     }))
 }
 
-fn parse_part<'a>(
+/// Parse header of a body part (or an embedded message)
+///
+/// `default_type` is used when the part has no Content-Type of its own.
+pub(super) fn parse_part<'a>(
     from: &Unparsed,
     errors: &mut Errors,
     part: &'a [u8],
     has_transfer_encoding: bool,
+    default_type: ContentType<'a>,
 ) -> Result<Unparsed<'a>, Located<SyntaxError>> {
-    let (header, body) = separate_message(part);
-    let mut header = Buffer::new(header);
+    let (header_data, body) = separate_message(part);
+    let mut header = Buffer::new(header_data);
 
     let mut version = None;
     let mut content_type = None;
     let mut transfer_encoding = None;
     let mut id = None;
     let mut description = None;
+    let mut disposition = None;
 
     while !header.is_empty() {
         let location = header.location();
@@ -147,8 +225,14 @@ fn parse_part<'a>(
             Ok(field) => field,
             Err(error) => {
                 log::trace!("error parsing field: {error}");
-                let (field, _) = mail::optional_field(&mut header)?;
-                errors.add(error.map(|error| ParseFieldError { field, error }));
+                match mail::optional_field(&mut header) {
+                    Ok((field, _)) =>
+                        errors.add(error.map(|error| ParseFieldError { field, error })),
+                    Err(error) => {
+                        errors.recover(error)?;
+                        skip_field(&mut header);
+                    }
+                }
                 continue;
             }
         };
@@ -162,7 +246,7 @@ fn parse_part<'a>(
             Header::Version(value) =>
                 version.set_once(errors, location, "MIME-Version", value),
             Header::ContentType(value) =>
-                content_type.set_once(errors, location, "Content-Type", value),
+                content_type.set_once(errors, location, "Content-Type", Located::new(location, value)),
             Header::ContentTransferEncoding(value) => {
                 if has_transfer_encoding {
                     errors.add(Located::<Error>::new(location, Error::NestedTransferEncoding));
@@ -174,13 +258,19 @@ fn parse_part<'a>(
                 id.set_once(errors, location, "Content-ID", value),
             Header::ContentDescription(value) =>
                 description.set_once(errors, location, "Content-Description", value),
+            Header::ContentDisposition(value) =>
+                disposition.set_once(errors, location, "Content-Disposition", value),
         }
     }
 
     Ok(super::Unparsed {
+        header: header_data,
+        id,
+        description,
+        disposition,
         data: body,
         version: version.unwrap_or(from.version),
-        content_type: content_type.unwrap_or_default(),
+        content_type: content_type.unwrap_or(Located::new(Location::ZERO, default_type)),
         transfer_encoding,
     })
 }
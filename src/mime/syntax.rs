@@ -54,19 +54,29 @@ impl Default for ContentType<'_> {
 }
 
 impl<'a> ContentType<'a> {
-    pub fn parameters(&self) -> impl Iterator<Item = Parameter<'a>> {
-        let mut buf = Buffer::new(self.parameters);
+    pub const MESSAGE_RFC822: ContentType<'static> = ContentType {
+        type_: "message",
+        subtype: "rfc822",
+        parameters: b"",
+    };
 
-        std::iter::from_fn(move || {
-            if buf.expect(b";").is_ok() {
-                Some(parameter(&mut buf).unwrap())
-            } else {
-                None
-            }
-        })
+    pub fn parameters(&self) -> impl Iterator<Item = Parameter<'a>> {
+        parameters(self.parameters)
     }
 }
 
+fn parameters(data: &[u8]) -> impl Iterator<Item = Parameter<'_>> {
+    let mut buf = Buffer::new(data);
+
+    std::iter::from_fn(move || {
+        if buf.expect(b";").is_ok() {
+            Some(parameter(&mut buf).unwrap())
+        } else {
+            None
+        }
+    })
+}
+
 pub fn content_type<'a>(buf: &mut Buffer<'a>) -> Result<ContentType<'a>> {
     // content := "Content-Type" ":" type "/" subtype *(";" parameter)
     buf.atomic(|buf| {
@@ -181,6 +191,7 @@ pub enum Header<'a> {
     ContentTransferEncoding(TransferEncoding),
     ContentId(mail::MessageIdRef<'a>),
     ContentDescription(mail::Folded<'a>),
+    ContentDisposition(ContentDisposition<'a>),
 }
 
 pub fn header<'a>(name: &str, buf: &mut Buffer<'a>) -> Result<Option<Header<'a>>> {
@@ -194,11 +205,48 @@ pub fn header<'a>(name: &str, buf: &mut Buffer<'a>) -> Result<Option<Header<'a>>
         Header::ContentId(mail::msg_id(buf)?)
     } else if name.eq_ignore_ascii_case("Content-Description") {
         Header::ContentDescription(mail::unstructured(buf)?)
+    } else if name.eq_ignore_ascii_case("Content-Disposition") {
+        Header::ContentDisposition(content_disposition(buf)?)
     } else {
         return Ok(None);
     }))
 }
 
+// -------------------------- RFC 2183: The Content-Disposition Header Field ---
+
+#[derive(Clone, Copy, Debug)]
+pub struct ContentDisposition<'a> {
+    pub type_: &'a str,
+    pub parameters: &'a [u8],
+}
+
+impl<'a> ContentDisposition<'a> {
+    pub fn parameters(&self) -> impl Iterator<Item = Parameter<'a>> {
+        parameters(self.parameters)
+    }
+}
+
+pub fn content_disposition<'a>(buf: &mut Buffer<'a>) -> Result<ContentDisposition<'a>> {
+    // disposition := "Content-Disposition" ":" disposition-type
+    //                *(";" disposition-parm)
+    // disposition-type := "inline" / "attachment" / extension-token
+    buf.atomic(|buf| {
+        buf.maybe(mail::cfws);
+        let type_ = token(buf)?;
+        buf.maybe(mail::cfws);
+
+        let parameters = buf.take_matching(|buf| {
+            while buf.expect(b";").is_ok() {
+                parameter(buf)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(ContentDisposition { type_, parameters })
+    })
+}
+
 // --- RFC 2047: MIME Part Three: Message Header Extensions for Non-ASCII Text -
 
 #[derive(Clone, Copy, Debug)]
@@ -216,7 +264,9 @@ pub enum WordEncoding {
 
 impl<'a> EncodedWord<'a> {
     pub fn decode(self) -> Result<String, CharsetError> {
-        let charset = Charset::by_name(self.charset).ok_or(CharsetError)?;
+        // Charset may be followed by a language tag (RFC 2231, section 5).
+        let charset = self.charset.split('*').next().unwrap_or_default();
+        let charset = Charset::by_name(charset).ok_or(CharsetError)?;
 
         let data = match self.encoding {
             WordEncoding::Base64 => base64::decode(self.encoded_text).map_err(|_| CharsetError)?,
@@ -232,7 +282,7 @@ impl<'a> EncodedWord<'a> {
                             result.push(32);
                             rest = &rest[inx + 1..];
                         }
-                        b'=' if rest.len() > inx + 3 => {
+                        b'=' if rest.len() >= inx + 3 => {
                             let byte = std::str::from_utf8(&rest[inx + 1..inx + 3])
                                 .map_err(|_| CharsetError)?;
                             let byte = u8::from_str_radix(byte, 16)
@@ -281,3 +331,22 @@ pub fn encoded_word<'a>(buf: &mut Buffer<'a>) -> Result<EncodedWord<'a>> {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_word_decode() {
+        let decode = |data: &'static str| {
+            let mut buf = Buffer::new(data.as_bytes());
+            encoded_word(&mut buf).unwrap().decode()
+        };
+
+        assert_eq!(decode("=?UTF-8?Q?=C5=BC=C3=B3=C5=82w?=").unwrap(), "żółw");
+        assert_eq!(decode("=?ISO-8859-2?Q?=BF=F3=B3w_x?=").unwrap(), "żółw x");
+        assert_eq!(decode("=?UTF-8?B?xbzDs8WCdw==?=").unwrap(), "żółw");
+        assert!(decode("=?UTF-8?Q?a=C?=").is_err());
+        assert!(decode("=?UTF-8?Q?a=?=").is_err());
+    }
+}
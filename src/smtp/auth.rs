@@ -0,0 +1,206 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Implementation of [RFC 4954](https://datatracker.ietf.org/doc/html/rfc4954):
+//! SMTP Service Extension for Authentication
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::config::{self, AuthPolicy};
+
+/// Mechanisms advertised in response to EHLO
+pub const MECHANISMS: &str = "PLAIN LOGIN CRAM-MD5";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mechanism {
+    /// RFC 4616
+    Plain,
+    /// draft-murchison-sasl-login
+    Login,
+    /// RFC 2195
+    CramMd5,
+}
+
+impl Mechanism {
+    pub fn by_name(name: &str) -> Option<Mechanism> {
+        Some(match_ignore_ascii_case! { name;
+            "PLAIN" => Mechanism::Plain,
+            "LOGIN" => Mechanism::Login,
+            "CRAM-MD5" => Mechanism::CramMd5,
+            _ => return None,
+        })
+    }
+}
+
+/// State of an authentication exchange
+pub enum Exchange {
+    /// Waiting for PLAIN message
+    Plain,
+    /// Waiting for LOGIN username
+    LoginUsername,
+    /// Waiting for LOGIN password
+    LoginPassword {
+        username: String,
+    },
+    /// Waiting for CRAM-MD5 digest
+    CramMd5 {
+        challenge: String,
+    },
+}
+
+pub enum Step {
+    /// Send a challenge to the client and wait for its response
+    Challenge(Exchange, String),
+    /// Exchange finished and credentials are ready to be verified
+    Done(Credentials),
+}
+
+pub enum Credentials {
+    Plain {
+        username: String,
+        password: String,
+    },
+    CramMd5 {
+        username: String,
+        challenge: String,
+        digest: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Invalid base64 data")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Invalid UTF-8 data")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("Malformed authentication response")]
+    Malformed,
+    #[error("Initial response not allowed for this mechanism")]
+    UnexpectedInitialResponse,
+}
+
+impl Exchange {
+    /// Begin a new exchange, optionally with an initial response
+    pub fn start(mechanism: Mechanism, initial: Option<&str>) -> Result<Step, Error> {
+        let exchange = match mechanism {
+            Mechanism::Plain => Exchange::Plain,
+            Mechanism::Login => Exchange::LoginUsername,
+            Mechanism::CramMd5 => Exchange::CramMd5 {
+                challenge: format!("<{}.{}@smtp-test-server>",
+                    std::process::id(), OffsetDateTime::now_utc().unix_timestamp_nanos()),
+            },
+        };
+
+        match initial {
+            Some(_) if mechanism == Mechanism::CramMd5 => Err(Error::UnexpectedInitialResponse),
+            // Zero-length initial response is sent as a single "="
+            Some("=") => exchange.respond(""),
+            Some(response) => exchange.respond(response),
+            None => Ok(exchange.challenge()),
+        }
+    }
+
+    fn challenge(self) -> Step {
+        let challenge = match self {
+            Exchange::Plain => String::new(),
+            Exchange::LoginUsername => "Username:".into(),
+            Exchange::LoginPassword { .. } => "Password:".into(),
+            Exchange::CramMd5 { ref challenge } => challenge.clone(),
+        };
+        Step::Challenge(self, challenge)
+    }
+
+    /// Process a base64-encoded response from the client
+    pub fn respond(self, response: &str) -> Result<Step, Error> {
+        let response = String::from_utf8(base64::decode(response)?)?;
+
+        Ok(match self {
+            Exchange::Plain => {
+                // message = [authzid] UTF8NUL authcid UTF8NUL passwd
+                let mut parts = response.splitn(3, '\0');
+                let _authzid = parts.next().ok_or(Error::Malformed)?;
+                let username = parts.next().ok_or(Error::Malformed)?;
+                let password = parts.next().ok_or(Error::Malformed)?;
+
+                Step::Done(Credentials::Plain {
+                    username: username.into(),
+                    password: password.into(),
+                })
+            }
+            Exchange::LoginUsername =>
+                Exchange::LoginPassword { username: response }.challenge(),
+            Exchange::LoginPassword { username } =>
+                Step::Done(Credentials::Plain { username, password: response }),
+            Exchange::CramMd5 { challenge } => {
+                let (username, digest) = response.rsplit_once(' ').ok_or(Error::Malformed)?;
+
+                Step::Done(Credentials::CramMd5 {
+                    username: username.into(),
+                    challenge,
+                    digest: digest.to_ascii_lowercase(),
+                })
+            }
+        })
+    }
+}
+
+impl Credentials {
+    pub fn username(&self) -> &str {
+        match self {
+            Credentials::Plain { username, .. } | Credentials::CramMd5 { username, .. } => username,
+        }
+    }
+
+    /// Verify these credentials against configured policy
+    pub fn verify(&self, config: &config::Auth) -> bool {
+        match config.policy {
+            AuthPolicy::AcceptAny => true,
+            AuthPolicy::Reject => false,
+            AuthPolicy::Users => match config.users.get(self.username()) {
+                Some(password) => self.matches(password),
+                None => false,
+            },
+        }
+    }
+
+    fn matches(&self, expected: &str) -> bool {
+        match self {
+            Credentials::Plain { password, .. } => password == expected,
+            Credentials::CramMd5 { challenge, digest, .. } => {
+                let mut mac = Hmac::<Md5>::new_from_slice(expected.as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(challenge.as_bytes());
+
+                let expected = mac.finalize()
+                    .into_bytes()
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>();
+
+                *digest == expected
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cram_md5() {
+        // Example from RFC 2195 section 2
+        let credentials = Credentials::CramMd5 {
+            username: "tim".into(),
+            challenge: "<1896.697170952@postoffice.reston.mci.net>".into(),
+            digest: "b913a602c7eda7a495b4e6e7334d3890".into(),
+        };
+
+        assert!(credentials.matches("tanstaaftanstaaf"));
+        assert!(!credentials.matches("tanstaaf"));
+    }
+}
@@ -0,0 +1,442 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Implementation of [RFC 3461](https://datatracker.ietf.org/doc/html/rfc3461):
+//! SMTP Service Extension for Delivery Status Notifications
+
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write as _, net::SocketAddr, str::FromStr};
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{mail, state::{Envelope, Message}};
+use super::{DomainOrAddr, ForwardPath, ReversePath};
+
+/// Delivery status notification parameters of a mail transaction
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dsn {
+    /// How much of the message should be returned in a failure notification
+    /// (RET)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ret: Option<Return>,
+    /// Envelope identifier (ENVID), still xtext-encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub envelope_id: Option<String>,
+    /// Parameters of each recipient, in the same order as [`Envelope::to`]
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Return {
+    /// Entire message (RET=FULL)
+    Full,
+    /// Only the header section (RET=HDRS)
+    Headers,
+}
+
+/// Delivery status notification parameters of a single recipient
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recipient {
+    /// Conditions under which notifications should be sent (NOTIFY)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<Notify>,
+    /// Original recipient address (ORCPT)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<OriginalRecipient>,
+}
+
+/// Value of the NOTIFY parameter, with all conditions unset for NEVER
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Notify {
+    pub success: bool,
+    pub failure: bool,
+    pub delay: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OriginalRecipient {
+    /// Type of the address, such as `rfc822`
+    pub address_type: String,
+    /// Address, still xtext-encoded
+    pub address: String,
+}
+
+impl Dsn {
+    /// Did client provide no DSN parameters?
+    pub fn is_empty(&self) -> bool {
+        self.ret.is_none()
+            && self.envelope_id.is_none()
+            && self.recipients.iter().all(|recipient| {
+                recipient.notify.is_none() && recipient.original.is_none()
+            })
+    }
+}
+
+impl FromStr for Return {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // ret-value = "FULL" / "HDRS"
+        match_ignore_ascii_case! { value;
+            "FULL" => Ok(Return::Full),
+            "HDRS" => Ok(Return::Headers),
+            _ => Err("expected one of FULL or HDRS"),
+        }
+    }
+}
+
+impl FromStr for Notify {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // notify-esmtp-value  = "NEVER" / 1#notify-list-element
+        // notify-list-element = "SUCCESS" / "FAILURE" / "DELAY"
+        if value.eq_ignore_ascii_case("NEVER") {
+            return Ok(Notify::default());
+        }
+
+        let mut notify = Notify::default();
+
+        for element in value.split(',') {
+            let condition = match_ignore_ascii_case! { element;
+                "SUCCESS" => &mut notify.success,
+                "FAILURE" => &mut notify.failure,
+                "DELAY" => &mut notify.delay,
+                _ => return Err("expected NEVER, or a list of SUCCESS, FAILURE, and DELAY"),
+            };
+
+            if *condition {
+                return Err("duplicate condition");
+            }
+
+            *condition = true;
+        }
+
+        Ok(notify)
+    }
+}
+
+impl FromStr for OriginalRecipient {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // orcpt-parameter = "ORCPT=" original-recipient-address
+        // original-recipient-address = addr-type ";" xtext
+        let (address_type, address) = value.split_once(';')
+            .ok_or("expected address type followed by ';'")?;
+
+        if address_type.is_empty() {
+            return Err("expected address type");
+        }
+
+        Ok(OriginalRecipient {
+            address_type: address_type.to_ascii_lowercase(),
+            address: xtext(address)?,
+        })
+    }
+}
+
+/// Check that a value is valid xtext, and return it still encoded
+///
+/// Values are kept encoded, as that is how they are written in delivery
+/// status notifications (RFC 3464 sections 2.2.1 and 2.3.1), and so that
+/// decoded line breaks can't end up in one.
+pub fn xtext(value: &str) -> Result<String, &'static str> {
+    decode_xtext(value).map(|_| value.into())
+}
+
+/// Decode xtext, in which `+` and characters outside of `!`–`~` are written
+/// as `+` followed by two upper case hexadecimal digits
+pub fn decode_xtext(value: &str) -> Result<String, &'static str> {
+    // xtext    = *( xchar / hexchar )
+    // xchar    = any ASCII CHAR between "!" (33) and "~" (126) inclusive,
+    //            except for "+" and "=".
+    // hexchar  = ASCII "+" immediately followed by two upper case
+    //            hexadecimal digits
+    let mut result = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'+' => {
+                let hex = tail.get(..2)
+                    .filter(|hex| hex.iter().all(|&c| matches!(c, b'0'..=b'9' | b'A'..=b'F')))
+                    .ok_or("invalid escape sequence")?;
+                result.push(u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap());
+                rest = &tail[2..];
+            }
+            b'=' => return Err("unescaped '='"),
+            _ => {
+                result.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8(result).map_err(|_| "invalid UTF-8")
+}
+
+/// Generate a delivery status notification (RFC 3464) reporting that
+/// a message was delivered
+///
+/// `mta` is the address at which the message was received. This server has
+/// no DNS name, so the report identifies it by IP address, using a private
+/// name type (RFC 3464 section 2.1.2).
+///
+/// Returns `None` when no recipient requested to be notified about
+/// successful delivery, or when the message has no reverse path to which to
+/// send a notification.
+///
+/// [`Dsn::ret`] only applies to failure notifications (RFC 3461 section 4.3),
+/// so only the header section of the message is included.
+pub fn delivery_report(message: &Message, mta: SocketAddr) -> Option<(Envelope, Vec<u8>)> {
+    let envelope = &message.envelope;
+    let dsn = envelope.dsn.as_ref()?;
+
+    let to = match envelope.from {
+        ReversePath::Null => return None,
+        ReversePath::Mailbox(ref mailbox) => mailbox,
+    };
+
+    let recipients = envelope.to.iter()
+        .zip(&dsn.recipients)
+        .filter(|(_, recipient)| recipient.notify.is_some_and(|notify| notify.success))
+        .collect::<Vec<_>>();
+
+    if recipients.is_empty() {
+        return None;
+    }
+
+    let ip = mta.ip().to_canonical();
+    let host = DomainOrAddr::Addr(ip);
+    let now = OffsetDateTime::now_utc();
+    let boundary = format!("dsn-{}", now.unix_timestamp_nanos());
+    let (headers, _) = mail::separate_message(&message.raw);
+
+    // Addresses of recipients may contain UTF-8 if they were sent with
+    // SMTPUTF8, in which case a global delivery status must be used
+    // (RFC 6533 section 6.2).
+    let report_type = if recipients.iter().all(|(address, _)| address.to_string().is_ascii()) {
+        "delivery-status"
+    } else {
+        "global-delivery-status"
+    };
+
+    let mut report = String::new();
+
+    let _ = write!(report, "From: Mail Delivery System <MAILER-DAEMON@{host}>\r\n\
+        To: <{to}>\r\n\
+        Subject: Successful Mail Delivery Report\r\n\
+        Date: {}\r\n\
+        Message-ID: <dsn.{}>\r\n\
+        Auto-Submitted: auto-replied\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/report; report-type={report_type}; boundary=\"{boundary}\"\r\n\
+        \r\n\
+        --{boundary}\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        \r\n\
+        Your message was successfully delivered to the following recipients:\r\n\
+        \r\n",
+        format_date(now), message.id);
+
+    for (address, _) in &recipients {
+        let _ = write!(report, "    {address}\r\n");
+    }
+
+    let _ = write!(report, "\r\n\
+        --{boundary}\r\n\
+        Content-Type: message/{report_type}\r\n\
+        \r\n\
+        Reporting-MTA: x-ip; {ip}\r\n");
+
+    if let Some(ref id) = dsn.envelope_id {
+        let _ = write!(report, "Original-Envelope-Id: {id}\r\n");
+    }
+
+    for (address, recipient) in &recipients {
+        let address = address.to_string();
+        let address_type = if address.is_ascii() { "rfc822" } else { "utf-8" };
+        let _ = write!(report, "\r\nFinal-Recipient: {address_type}; {address}\r\n");
+
+        if let Some(ref original) = recipient.original {
+            let _ = write!(report, "Original-Recipient: {}; {}\r\n",
+                original.address_type, original.address);
+        }
+
+        let _ = write!(report, "Action: delivered\r\nStatus: 2.0.0\r\n");
+    }
+
+    // Header section may contain UTF-8 if it was sent with SMTPUTF8
+    // (RFC 6533 section 6.3).
+    let headers_type = if headers.is_ascii() {
+        "text/rfc822-headers"
+    } else {
+        "message/global-headers"
+    };
+
+    let _ = write!(report, "\r\n\
+        --{boundary}\r\n\
+        Content-Type: {headers_type}\r\n\
+        \r\n");
+
+    let mut report = report.into_bytes();
+    report.extend_from_slice(headers);
+    report.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    let envelope = Envelope {
+        client: None,
+        remote: mta,
+        tls: false,
+        user: None,
+        from: ReversePath::Null,
+        to: vec![ForwardPath::Mailbox(to.clone())],
+        received: now,
+        dsn: None,
+    };
+
+    Some((envelope, report))
+}
+
+/// Format date as in RFC 5322 section 3.3, in UTC
+fn format_date(date: OffsetDateTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let date = date.to_offset(UtcOffset::UTC);
+    format!("{} {} {} {:02}:{:02}:{:02} +0000",
+        date.day(), MONTHS[usize::from(u8::from(date.month())) - 1], date.year(),
+        date.hour(), date.minute(), date.second())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Parsing, state::State, storage::Storage, syntax::Buffer};
+    use super::super::syntax;
+
+    #[test]
+    fn xtext() {
+        assert_eq!(decode_xtext("QQ+2B314159").unwrap(), "QQ+314159");
+        assert_eq!(decode_xtext("a+0D+0Ab").unwrap(), "a\r\nb");
+        assert_eq!(decode_xtext("+C5+BC+C3+B3+C5+82w").unwrap(), "żółw");
+        assert!(decode_xtext("+2").is_err());
+        assert!(decode_xtext("+2b").is_err());
+        assert!(decode_xtext("a=b").is_err());
+        assert!(decode_xtext("+C5").is_err());
+
+        assert_eq!(super::xtext("foo+0D+0ABcc:x").unwrap(), "foo+0D+0ABcc:x");
+    }
+
+    fn envelope(from: &str, to: &[&str], dsn: Dsn) -> Envelope {
+        Envelope {
+            from: syntax::reverse_path(&mut Buffer::new(from.as_bytes())).unwrap().to_owned(),
+            to: to.iter()
+                .map(|to| syntax::forward_path(&mut Buffer::new(to.as_bytes())).unwrap().to_owned())
+                .collect(),
+            dsn: Some(dsn),
+            ..crate::state::tests::envelope()
+        }
+    }
+
+    #[tokio::test]
+    async fn delivery_report() {
+        let notify = |success| Recipient {
+            notify: Some(Notify { success, failure: true, delay: false }),
+            original: None,
+        };
+        let dsn = Dsn {
+            ret: Some(Return::Full),
+            envelope_id: Some("QQ+0D+0ABcc:x".into()),
+            recipients: vec![
+                Recipient {
+                    original: Some("rfc822;a+2Bx@example.com".parse().unwrap()),
+                    ..notify(true)
+                },
+                notify(false),
+                notify(true),
+            ],
+        };
+        let envelope = envelope(
+            "<sender@example.com>",
+            &["<a@example.com>", "<b@example.com>", "<żółw@example.com>"],
+            dsn,
+        );
+        let message = "From: sender@example.com\r\n\
+            Subject: Zażółć gęślą jaźń\r\n\
+            Date: Fri, 16 Oct 2026 10:00:00 +0000\r\n\
+            Message-ID: <report@example.com>\r\n\
+            \r\n\
+            Body is not included\r\n";
+
+        let state = State::new(Storage::Memory);
+        let message = state.submit_message(envelope, message.as_bytes(), Parsing::Strict)
+            .await
+            .unwrap();
+
+        let (envelope, report) = super::delivery_report(&message, message.envelope.remote)
+            .unwrap();
+        let text = std::str::from_utf8(&report).unwrap();
+
+        assert!(matches!(envelope.from, ReversePath::Null));
+        assert_eq!(envelope.to.len(), 1);
+        assert_eq!(envelope.to[0].to_string(), "sender@example.com");
+
+        assert!(text.contains("report-type=global-delivery-status"));
+        assert!(text.contains("\r\nContent-Type: message/global-delivery-status\r\n"));
+        assert!(text.contains("\r\nContent-Type: message/global-headers\r\n"));
+        assert!(text.contains("\r\nReporting-MTA: x-ip; 127.0.0.1\r\n"));
+        // Values are written as they were sent, so that they can't introduce
+        // new fields.
+        assert!(text.contains("\r\nOriginal-Envelope-Id: QQ+0D+0ABcc:x\r\n"));
+        assert!(text.contains("\r\n\r\nFinal-Recipient: rfc822; a@example.com\r\n\
+            Original-Recipient: rfc822; a+2Bx@example.com\r\n\
+            Action: delivered\r\n\
+            Status: 2.0.0\r\n"));
+        assert!(!text.contains("b@example.com"));
+        assert!(text.contains("\r\n\r\nFinal-Recipient: utf-8; żółw@example.com\r\n"));
+        assert!(text.contains("\r\nSubject: Zażółć gęślą jaźń\r\n"));
+        assert!(!text.contains("Body is not included"));
+
+        let report = state.submit_message(envelope, &report, Parsing::Strict).await.unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.id, "dsn.report@example.com");
+    }
+
+    #[tokio::test]
+    async fn no_report() {
+        let state = State::new(Storage::Memory);
+        let submit = |id, from, success| {
+            let dsn = Dsn {
+                recipients: vec![Recipient {
+                    notify: Some(Notify { success, failure: true, delay: true }),
+                    original: None,
+                }],
+                ..Dsn::default()
+            };
+            let message = format!("From: sender@example.com\r\n\
+                Date: Fri, 16 Oct 2026 10:00:00 +0000\r\n\
+                Message-ID: <{id}@example.com>\r\n\
+                \r\n");
+            let envelope = envelope(from, &["<a@example.com>"], dsn);
+            let state = state.clone();
+            async move {
+                state.submit_message(envelope, message.as_bytes(), Parsing::Strict).await.unwrap()
+            }
+        };
+
+        // Nobody asked to be notified about success.
+        let message = submit("failure", "<sender@example.com>", false).await;
+        assert!(super::delivery_report(&message, message.envelope.remote).is_none());
+
+        // There is nobody to notify.
+        let message = submit("null", "<>", true).await;
+        assert!(super::delivery_report(&message, message.envelope.remote).is_none());
+    }
+}
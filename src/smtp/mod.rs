@@ -2,7 +2,13 @@
 // Licensed under the MIT license. See LICENSE file in the project root for
 // full license text.
 
+pub mod dsn;
 pub mod server;
 
+mod auth;
 mod proto;
+mod status;
 mod syntax;
+mod tls;
+
+pub use self::{status::Status, syntax::{DomainOrAddr, ForwardPath, ReversePath}};
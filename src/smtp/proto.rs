@@ -4,19 +4,47 @@
 
 //! SMTP protocol state machine
 
+use memchr::memmem;
 use std::{io::Write as _, fmt, net::SocketAddr, mem};
 use thiserror::Error;
+use time::OffsetDateTime;
 
-use crate::{syntax::*, state::StateRef, util};
-use super::syntax::{self, DomainRefOrAddr, ForwardPathRef, ReversePathRef, ReversePath, ForwardPath};
+use crate::{syntax::*, state::{StateRef, Envelope}, util, config::{self, ListenerMode, Parsing}};
+use super::{auth, dsn::{self, Dsn}, status::Status, syntax::{
+    self, DomainRefOrAddr, DomainOrAddr, ForwardPathRef, ReversePathRef, ReversePath, ForwardPath,
+}};
 
 pub struct Connection {
     global: StateRef,
     name: SocketAddr,
     remote: SocketAddr,
+    /// Is this connection encrypted?
+    tls: bool,
+    /// Can this connection be upgraded using STARTTLS?
+    starttls: bool,
+    /// How to handle malformed messages
+    parsing: Parsing,
     state: State,
+    /// Name client introduced itself with in EHLO/HELO
+    client: Option<DomainOrAddr>,
+    /// Authentication configuration
+    auth: config::Auth,
+    /// Authentication exchange in progress
+    sasl: Option<auth::Exchange>,
+    /// Name of the user client has authenticated as
+    user: Option<String>,
     reverse_path: Option<ReversePath>,
     forward_path: Vec<ForwardPath>,
+    /// Type of body declared for current mail transaction
+    body: BodyType,
+    /// Did client request SMTPUTF8 for current mail transaction?
+    smtputf8: bool,
+    /// BDAT chunk being received
+    chunk: Option<Chunk>,
+    /// Delivery status notification parameters of current mail transaction
+    dsn: Dsn,
+    /// Should delivery reports be generated for accepted messages?
+    delivery_reports: bool,
     /// Line buffer
     line: Vec<u8>,
     /// Message buffer
@@ -32,6 +60,8 @@ pub struct Response<'a> {
     pub data: &'a [u8],
     /// Should connection be closed after sending this response?
     pub close_connection: bool,
+    /// Should TLS negotiation begin after sending this response?
+    pub start_tls: bool,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -44,18 +74,50 @@ enum State {
     Recipients,
     /// Client is sending message body
     Data,
+    /// Client is sending message body in chunks (RFC 3030), and has not yet
+    /// sent the last one
+    Chunks,
+}
+
+/// BDAT chunk being received (RFC 3030)
+struct Chunk {
+    /// Number of octets yet to be received
+    remaining: usize,
+    /// Is this the last chunk of the message?
+    last: bool,
+    /// Was BDAT issued out of sequence? Contents of such chunk are discarded.
+    discard: bool,
+    /// Did this chunk exceed the maximum message size?
+    overflow: bool,
 }
 
 impl Connection {
-    pub fn new(config: &crate::config::Smtp, global: StateRef, name: SocketAddr, remote: SocketAddr)
-    -> Connection {
+    pub fn new(
+        config: &config::Smtp,
+        listener: config::Listener,
+        global: StateRef,
+        name: SocketAddr,
+        remote: SocketAddr,
+    ) -> Connection {
         Connection {
             global,
             name,
             remote,
+            tls: listener.mode == ListenerMode::ImplicitTls,
+            starttls: listener.mode == ListenerMode::StartTls,
+            parsing: listener.parsing,
             state: State::Handshake,
+            client: None,
+            auth: config.auth.clone(),
+            sasl: None,
+            user: None,
             reverse_path: None,
             forward_path: vec![],
+            body: BodyType::SevenBit,
+            smtputf8: false,
+            chunk: None,
+            dsn: Dsn::default(),
+            delivery_reports: config.delivery_reports,
             // RFC 5321 section 4.5.3.1.6 specifies 1000 octets as smallest
             // allowed upper limit on length of a single line.
             line: Vec::with_capacity(1000),
@@ -66,7 +128,16 @@ impl Connection {
     }
 
     pub fn connect(&mut self) -> Response {
-        Response::new(&mut self.response, 220, format!("{} Service ready", self.name))
+        Response::new(&mut self.response, 220, None, format!("{} Service ready", self.name))
+    }
+
+    /// Number of octets of a BDAT chunk yet to be received, if one is being
+    /// received
+    ///
+    /// While this is `Some` all data received from the client should be passed
+    /// to [`Connection::chunk`] instead of [`Connection::line`].
+    pub fn chunk_remaining(&self) -> Option<usize> {
+        self.chunk.as_ref().map(|chunk| chunk.remaining)
     }
 
     pub fn buffer(&mut self) -> &mut Vec<u8> {
@@ -94,33 +165,61 @@ impl Connection {
 
         log::trace!(">> {}", util::maybe_ascii(&self.line));
 
-        if !self.line.iter().all(u8::is_ascii) {
-            return Some(Response::INVALID_CHARACTERS);
-        }
-
         let new_line = Vec::with_capacity(self.line.capacity());
         let line = mem::replace(&mut self.line, new_line);
 
+        let ascii = line.iter().all(u8::is_ascii);
+
+        if let Some(exchange) = self.sasl.take() {
+            // A response which isn't base64 cancels the exchange (RFC 4954
+            // section 4).
+            if !ascii {
+                return Some(Response::CANNOT_DECODE_BASE64);
+            }
+
+            let response = std::str::from_utf8(&line).unwrap().trim_end_matches("\r\n");
+            return Some(self.auth_response(exchange, response));
+        }
+
+        // Non-ASCII characters are only allowed in addresses, and only after
+        // client requested SMTPUTF8 (RFC 6531).
+        if !ascii && std::str::from_utf8(&line).is_err() {
+            return Some(Response::INVALID_CHARACTERS);
+        }
+
         let command = match Command::parse(&line) {
             Ok(command) => command,
-            Err(err) => return Some(Response::new(&mut self.response, 500, err)),
+            Err(err) => return Some(Response::new(&mut self.response, err.code(), err.status(), err)),
         };
 
+        if !ascii {
+            match command {
+                Command::Mail(ref mail) if mail.smtputf8 => {}
+                Command::Recipient(_) if self.smtputf8 => {}
+                Command::Mail(_) | Command::Recipient(_) =>
+                    return Some(Response::NON_ASCII_ADDRESS),
+                _ => return Some(Response::INVALID_CHARACTERS),
+            }
+        }
+
         Some(match command {
             Command::Hello(hello) => self.handshake(hello),
             Command::Mail(mail) => self.mail(mail),
             Command::Recipient(recipient) => self.recipient(recipient),
             Command::Data => self.data(),
+            Command::BinaryData(bdat) => return self.binary_data(bdat).await,
             Command::Reset => self.reset(),
             Command::Verify(_) | Command::Expand(_) => Response::NOT_IMPLEMENTED,
             Command::Help(topic) => self.help(topic),
             Command::Noop => Response::OK_250,
             Command::Quit => self.close(),
+            Command::StartTls => self.start_tls(),
+            Command::Auth(auth) => self.auth(auth),
         })
     }
 
     pub fn close(&mut self) -> Response {
-        Response::new(&mut self.response, 221,
+        Response::new(&mut self.response, 221, Status::OK,
             format!("{} Service closing transmission channel", self.name)).close()
     }
 
@@ -129,12 +228,26 @@ impl Connection {
     fn handshake(&mut self, hello: Hello) -> Response {
         log::info!("client {:?} ({}) connected", hello.client, self.remote);
         self.reset_buffers();
+        self.client = Some(hello.client.to_owned());
 
-        let mut rsp = Response::new_multiline(&mut self.response, 250,
+        let mut rsp = Response::new_multiline(&mut self.response, 250, None,
                 format!("{} greets {}", self.name, hello.client));
 
         if hello.extended {
             rsp.line(format!("SIZE {}", self.message.capacity()));
+            rsp.line("PIPELINING");
+            rsp.line("8BITMIME");
+            rsp.line("SMTPUTF8");
+            rsp.line("CHUNKING");
+            rsp.line("BINARYMIME");
+            rsp.line("ENHANCEDSTATUSCODES");
+            rsp.line("DSN");
+
+            if self.starttls && !self.tls {
+                rsp.line("STARTTLS");
+            }
+
+            rsp.line(format!("AUTH {}", auth::MECHANISMS));
         }
 
         rsp.finish()
@@ -149,9 +262,13 @@ impl Connection {
 
         self.reset_buffers();
         self.reverse_path = Some(mail.from.to_owned());
+        self.body = mail.body.unwrap_or(BodyType::SevenBit);
+        self.smtputf8 = mail.smtputf8;
+        self.dsn.ret = mail.ret;
+        self.dsn.envelope_id = mail.envelope_id;
         self.state = State::Recipients;
 
-        Response::OK_250
+        Response::SENDER_OK
     }
 
     fn recipient(&mut self, recipient: Recipient) -> Response {
@@ -160,8 +277,9 @@ impl Connection {
         }
 
         self.forward_path.push(recipient.to.to_owned());
+        self.dsn.recipients.push(recipient.dsn);
 
-        Response::OK_250
+        Response::RECIPIENT_OK
     }
 
     async fn data_line(&mut self) -> Option<Response<'_>> {
@@ -187,10 +305,144 @@ impl Connection {
             return Response::BAD_SEQUENCE_OF_COMMANDS;
         }
 
+        // Binary data can't be dot-stuffed, so it can only be sent with BDAT
+        // (RFC 3030 section 3).
+        if self.body == BodyType::BinaryMime {
+            return Response::new(&mut self.response, 503, Status::INVALID_COMMAND,
+                "Bad sequence of commands - BINARYMIME requires BDAT");
+        }
+
         self.state = State::Data;
         Response::START_MAIL_INPUT
     }
 
+    async fn binary_data(&mut self, bdat: BinaryData) -> Option<Response<'_>> {
+        // Chunk must be received even if the command is rejected, as otherwise
+        // it would be interpreted as commands.
+        let discard = !matches!(self.state, State::Recipients | State::Chunks)
+            || self.forward_path.is_empty();
+
+        self.chunk = Some(Chunk {
+            remaining: bdat.size,
+            last: bdat.last,
+            discard,
+            overflow: false,
+        });
+
+        self.chunk(&[]).await
+    }
+
+    /// Handle octets of a BDAT chunk
+    ///
+    /// Returns a response once the entire chunk has been received.
+    pub async fn chunk(&mut self, data: &[u8]) -> Option<Response<'_>> {
+        let chunk = self.chunk.as_mut()?;
+        chunk.remaining -= data.len();
+
+        if !chunk.discard && !chunk.overflow {
+            if self.message.len() + data.len() > self.message.capacity() {
+                chunk.overflow = true;
+            } else {
+                self.message.extend_from_slice(data);
+            }
+        }
+
+        if chunk.remaining > 0 {
+            return None;
+        }
+
+        let chunk = self.chunk.take().unwrap();
+
+        if chunk.discard {
+            return Some(Response::BAD_SEQUENCE_OF_COMMANDS);
+        }
+
+        if chunk.overflow {
+            self.reset_buffers();
+            return Some(Response::TOO_MUCH_MAIL_DATA);
+        }
+
+        if chunk.last {
+            self.state = State::Relaxed;
+            self.message_length = self.message.len();
+            Some(self.submit_message().await)
+        } else {
+            self.state = State::Chunks;
+            Some(Response::OK_250)
+        }
+    }
+
+    fn start_tls(&mut self) -> Response<'_> {
+        if !self.starttls {
+            return Response::NOT_IMPLEMENTED;
+        }
+
+        if self.tls {
+            return Response::BAD_SEQUENCE_OF_COMMANDS;
+        }
+
+        Response::READY_TO_START_TLS
+    }
+
+    /// Notify this connection that TLS negotiation has finished
+    ///
+    /// Per RFC 3207 section 4.2 this discards all knowledge obtained from
+    /// the client prior to the TLS negotiation.
+    pub fn tls_started(&mut self) {
+        self.tls = true;
+        self.client = None;
+        self.sasl = None;
+        self.user = None;
+        self.reset_buffers();
+        self.line.clear();
+        self.state = State::Handshake;
+    }
+
+    fn auth(&mut self, auth: Auth) -> Response<'_> {
+        // RFC 4954 section 4: AUTH is not permitted during a mail transaction,
+        // nor after a successful AUTH.
+        if self.user.is_some() || self.state != State::Relaxed {
+            return Response::BAD_SEQUENCE_OF_COMMANDS;
+        }
+
+        let mechanism = match auth::Mechanism::by_name(auth.mechanism) {
+            Some(mechanism) => mechanism,
+            None => return Response::UNRECOGNIZED_AUTHENTICATION_TYPE,
+        };
+
+        let step = auth::Exchange::start(mechanism, auth.initial_response);
+        self.auth_step(step)
+    }
+
+    fn auth_response(&mut self, exchange: auth::Exchange, response: &str) -> Response<'_> {
+        if response == "*" {
+            return Response::AUTHENTICATION_CANCELLED;
+        }
+
+        self.auth_step(exchange.respond(response))
+    }
+
+    fn auth_step(&mut self, step: Result<auth::Step, auth::Error>) -> Response<'_> {
+        match step {
+            Err(err) => Response::new(&mut self.response, 501, Status::SYNTAX_ERROR, err),
+            Ok(auth::Step::Challenge(exchange, challenge)) => {
+                self.sasl = Some(exchange);
+                Response::new(&mut self.response, 334, None, base64::encode(challenge))
+            }
+            Ok(auth::Step::Done(credentials)) => {
+                if credentials.verify(&self.auth) {
+                    log::info!("client {} authenticated as {:?}", self.remote, credentials.username());
+                    self.user = Some(credentials.username().into());
+                    Response::AUTHENTICATION_SUCCEEDED
+                } else {
+                    log::info!("client {} failed to authenticate as {:?}",
+                        self.remote, credentials.username());
+                    Response::AUTHENTICATION_FAILED
+                }
+            }
+        }
+    }
+
     fn reset(&mut self) -> Response {
         self.reset_buffers();
         Response::OK_250
@@ -199,6 +451,9 @@ impl Connection {
     fn reset_buffers(&mut self) {
         self.reverse_path = None;
         self.forward_path.clear();
+        self.body = BodyType::SevenBit;
+        self.smtputf8 = false;
+        self.dsn = Dsn::default();
         self.state = State::Relaxed;
         self.message.clear();
         self.message_length = 0;
@@ -209,96 +464,238 @@ impl Connection {
             Some(topic) => topic,
             None => {
                 let mut rsp = Response::new_multiline(
-                    &mut self.response, 214, "Available commands:");
+                    &mut self.response, 214, Status::OK, "Available commands:");
                 rsp
                     .line("HELO")
                     .line("EHLO")
                     .line("MAIL")
                     .line("RCPT")
                     .line("DATA")
+                    .line("BDAT")
                     .line("RSET")
                     .line("HELP")
                     .line("NOOP")
                     .line("QUIT")
+                    .line("STARTTLS")
+                    .line("AUTH")
                 ;
                 return rsp.finish();
             }
         };
 
-        Response::new(&mut self.response, 504, format!("No help found for topic {topic:?}"))
+        Response::new(&mut self.response, 504, Status::INVALID_ARGUMENTS, format!("No help found for topic {topic:?}"))
     }
 
     // -------------------------------------------------- message processing ---
 
     async fn submit_message(&mut self) -> Response<'_> {
-        if !self.message.iter().all(u8::is_ascii) {
-            let at = self.message.iter().position(|c| !c.is_ascii()).unwrap();
-            log::trace!("not everything is ASCII: {} at {at}", self.message[at]);
-            return Response::INVALID_CHARACTERS;
+        let message = &self.message[..self.message_length];
+
+        // 8-bit data is allowed in body when client declared BODY=8BITMIME
+        // (RFC 6152) or BODY=BINARYMIME (RFC 3030), and also in header when it
+        // requested SMTPUTF8 (RFC 6531).
+        if let Some(at) = message.iter().position(|c| !c.is_ascii()) {
+            let header_length = memmem::find(message, b"\r\n\r\n")
+                .map_or(message.len(), |end| end + 2);
+            let allowed = self.smtputf8 || (
+                at >= header_length && self.body != BodyType::SevenBit);
+
+            if !allowed {
+                log::trace!("not everything is ASCII: {} at {at}", message[at]);
+                return if at < header_length {
+                    Response::NON_ASCII_HEADER
+                } else {
+                    Response::INVALID_CHARACTERS_IN_MESSAGE
+                };
+            }
         }
 
-        match self.global.submit_message(&self.message[..self.message_length]).await {
-            Ok(()) => Response::OK_250,
-            Err(err) => Response::new(&mut self.response, err.code(), err),
+        let envelope = Envelope {
+            client: self.client.clone(),
+            remote: self.remote,
+            tls: self.tls,
+            user: self.user.clone(),
+            from: self.reverse_path.take().unwrap_or(ReversePath::Null),
+            to: mem::take(&mut self.forward_path),
+            received: OffsetDateTime::now_utc(),
+            dsn: Some(mem::take(&mut self.dsn)).filter(|dsn| !dsn.is_empty()),
+        };
+
+        let message = &self.message[..self.message_length];
+
+        let message = match self.global.submit_message(envelope, message, self.parsing).await {
+            Ok(message) => message,
+            Err(err) => return Response::new(&mut self.response, err.code(), err.status(), err),
+        };
+
+        if !self.delivery_reports {
+            return Response::MESSAGE_ACCEPTED;
         }
+
+        if let Some((envelope, report)) = dsn::delivery_report(&message, self.name) {
+            if let Err(err) = self.global.submit_message(envelope, &report, self.parsing).await {
+                log::error!("could not store delivery report for {}: {err}", message.id);
+            }
+        }
+
+        Response::MESSAGE_ACCEPTED
     }
 }
 
 impl<'a> Response<'a> {
     const OK_250: Response<'static> = Response {
-        data: b"250 OK\r\n",
+        data: b"250 2.0.0 OK\r\n",
         close_connection: false,
+        start_tls: false,
+    };
+
+    const SENDER_OK: Response<'static> = Response {
+        data: b"250 2.1.0 Sender OK\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const RECIPIENT_OK: Response<'static> = Response {
+        data: b"250 2.1.5 Recipient OK\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const MESSAGE_ACCEPTED: Response<'static> = Response {
+        data: b"250 2.6.0 Message accepted\r\n",
+        close_connection: false,
+        start_tls: false,
     };
 
     const START_MAIL_INPUT: Response<'static> = Response {
         data: b"354 Start mail input; end with <CRLF>.<CRLF>\r\n",
         close_connection: false,
+        start_tls: false,
+    };
+
+    const READY_TO_START_TLS: Response<'static> = Response {
+        data: b"220 2.0.0 Ready to start TLS\r\n",
+        close_connection: false,
+        start_tls: true,
     };
 
     const NOT_IMPLEMENTED: Response<'static> = Response {
-        data: b"502 Command not implemented\r\n",
+        data: b"502 5.5.1 Command not implemented\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const AUTHENTICATION_SUCCEEDED: Response<'static> = Response {
+        data: b"235 2.7.0 Authentication succeeded\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const AUTHENTICATION_CANCELLED: Response<'static> = Response {
+        data: b"501 5.7.0 Authentication cancelled\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const CANNOT_DECODE_BASE64: Response<'static> = Response {
+        data: b"501 5.5.2 Cannot decode base64 response\r\n",
         close_connection: false,
+        start_tls: false,
+    };
+
+    const UNRECOGNIZED_AUTHENTICATION_TYPE: Response<'static> = Response {
+        data: b"504 5.5.4 Unrecognized authentication type\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const AUTHENTICATION_FAILED: Response<'static> = Response {
+        data: b"535 5.7.8 Authentication credentials invalid\r\n",
+        close_connection: false,
+        start_tls: false,
     };
 
     const INVALID_CHARACTERS: Response<'static> = Response {
-        data: b"500 Syntax error - invalid character\r\n",
+        data: b"500 5.5.2 Syntax error - invalid character\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const INVALID_CHARACTERS_IN_MESSAGE: Response<'static> = Response {
+        data: b"500 5.6.0 Syntax error - invalid character in message\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const NON_ASCII_ADDRESS: Response<'static> = Response {
+        data: b"553 5.6.7 Non-ASCII addresses require SMTPUTF8\r\n",
+        close_connection: false,
+        start_tls: false,
+    };
+
+    const NON_ASCII_HEADER: Response<'static> = Response {
+        data: b"554 5.6.7 Non-ASCII header fields require SMTPUTF8\r\n",
         close_connection: false,
+        start_tls: false,
     };
 
     const LINE_TOO_LONG: Response<'static> = Response {
-        data: b"500 Line too long\r\n",
+        data: b"500 5.5.2 Line too long\r\n",
         close_connection: false,
+        start_tls: false,
     };
 
     const BAD_SEQUENCE_OF_COMMANDS: Response<'static> = Response {
-        data: b"503 Bad sequence of commands\r\n",
+        data: b"503 5.5.1 Bad sequence of commands\r\n",
         close_connection: false,
+        start_tls: false,
     };
 
     const TOO_MUCH_MAIL_DATA: Response<'static> = Response {
-        data: b"552 Too much mail data\r\n",
+        data: b"552 5.3.4 Too much mail data\r\n",
         close_connection: false,
+        start_tls: false,
     };
 
     const MESSAGE_EXCEEDS_MAXIMUM_SIZE: Response<'static> = Response {
-        data: b"552 Message size exceeds fixed maximium message size",
+        data: b"552 5.3.4 Message size exceeds fixed maximium message size\r\n",
         close_connection: false,
+        start_tls: false,
     };
 
-    fn new(buffer: &'a mut Vec<u8>, code: u16, message: impl fmt::Display) -> Response<'a> {
+    /// Create a new response
+    ///
+    /// Enhanced status code (RFC 2034) should be present in all responses,
+    /// except for the greeting, replies to EHLO and HELO, and intermediate (3xx)
+    /// replies.
+    fn new(
+        buffer: &'a mut Vec<u8>,
+        code: u16,
+        status: impl Into<Option<Status>>,
+        message: impl fmt::Display,
+    ) -> Response<'a> {
         buffer.clear();
-        let _ = write!(buffer, "{code:03} {message}\r\n");
+        let _ = write!(buffer, "{code:03} ");
+        if let Some(status) = status.into() {
+            let _ = write!(buffer, "{status} ");
+        }
+        let _ = write!(buffer, "{message}\r\n");
         Response {
             data: buffer,
             close_connection: false,
+            start_tls: false,
         }
     }
 
-    fn new_multiline(buffer: &'a mut Vec<u8>, code: u16, message: impl fmt::Display)
-    -> ResponseBuilder<'a> {
-        buffer.clear();
-        let _ = write!(buffer, "{code:03} {message}\r\n");
-        ResponseBuilder { code, offset: 3, buffer }
+    fn new_multiline(
+        buffer: &'a mut Vec<u8>,
+        code: u16,
+        status: impl Into<Option<Status>>,
+        message: impl fmt::Display,
+    ) -> ResponseBuilder<'a> {
+        let status = status.into();
+        Response::new(buffer, code, status, message);
+        ResponseBuilder { code, status, offset: 3, buffer }
     }
 
     /// Set [`close_connection`] to `true`
@@ -309,6 +706,7 @@ impl<'a> Response<'a> {
 
 struct ResponseBuilder<'a> {
     code: u16,
+    status: Option<Status>,
     offset: usize,
     buffer: &'a mut Vec<u8>,
 }
@@ -318,13 +716,18 @@ impl<'a> ResponseBuilder<'a> {
         Response {
             data: self.buffer,
             close_connection: false,
+            start_tls: false,
         }
     }
 
     fn line(&mut self, line: impl fmt::Display) -> &mut Self {
         self.buffer[self.offset] = b'-';
         self.offset = self.buffer.len() + 3;
-        let _ = write!(self.buffer, "{:03} {line}\r\n", self.code);
+        let _ = write!(self.buffer, "{:03} ", self.code);
+        if let Some(status) = self.status {
+            let _ = write!(self.buffer, "{status} ");
+        }
+        let _ = write!(self.buffer, "{line}\r\n");
         self
     }
 }
@@ -334,12 +737,15 @@ enum Command<'a> {
     Mail(Mail<'a>),
     Recipient(Recipient<'a>),
     Data,
+    BinaryData(BinaryData),
     Reset,
     Verify(&'a str),
     Expand(&'a str),
     Help(Option<&'a str>),
     Noop,
     Quit,
+    StartTls,
+    Auth(Auth<'a>),
 }
 
 struct Hello<'a> {
@@ -351,10 +757,106 @@ struct Hello<'a> {
 struct Mail<'a> {
     from: ReversePathRef<'a>,
     size: Option<usize>,
+    body: Option<BodyType>,
+    /// Did client request SMTPUTF8 (RFC 6531)?
+    smtputf8: bool,
+    ret: Option<dsn::Return>,
+    envelope_id: Option<String>,
+}
+
+/// Type of message body, as declared with the BODY parameter (RFC 6152)
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum BodyType {
+    /// Body contains only 7-bit ASCII
+    SevenBit,
+    /// Body is a MIME message, which may contain 8-bit data
+    EightBitMime,
+    /// Body is a MIME message, which may contain binary data (RFC 3030)
+    BinaryMime,
+}
+
+impl std::str::FromStr for BodyType {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match_ignore_ascii_case! { value;
+            "7BIT" => Ok(BodyType::SevenBit),
+            "8BITMIME" => Ok(BodyType::EightBitMime),
+            "BINARYMIME" => Ok(BodyType::BinaryMime),
+            _ => Err("unsupported body type"),
+        }
+    }
+}
+
+struct BinaryData {
+    /// Size of the chunk, in octets
+    size: usize,
+    /// Is this the last chunk of the message?
+    last: bool,
+}
+
+struct Auth<'a> {
+    mechanism: &'a str,
+    initial_response: Option<&'a str>,
 }
 
 struct Recipient<'a> {
     to: ForwardPathRef<'a>,
+    dsn: dsn::Recipient,
+}
+
+/// Parameter of MAIL or RCPT command
+struct Parameter<'a> {
+    location: Location,
+    keyword: &'a str,
+    value: Option<&'a str>,
+}
+
+impl<'a> Parameter<'a> {
+    /// Parse next parameter, if there is one
+    fn next(line: &mut Buffer<'a>) -> Result<Option<Self>, CommandParseError> {
+        if line.expect(b" ").is_err() {
+            return Ok(None);
+        }
+
+        let location = line.location();
+        let (keyword, value) = syntax::parameter(line)?;
+        Ok(Some(Parameter { location, keyword, value }))
+    }
+
+    /// Parse value of this parameter into `slot`, unless it was already set
+    fn set_once<T, E: fmt::Display>(
+        &self,
+        slot: &mut Option<T>,
+        parse: impl FnOnce(&'a str) -> Result<T, E>,
+    ) -> Result<(), CommandParseError> {
+        if slot.is_some() {
+            return Err(self.duplicate());
+        }
+
+        let value = self.value.ok_or_else(
+            || self.error(format!("expected a value for {}", self.keyword)))?;
+
+        match parse(value) {
+            Ok(value) => {
+                *slot = Some(value);
+                Ok(())
+            }
+            Err(err) => Err(self.error(format!("invalid {} - {err}", self.keyword))),
+        }
+    }
+
+    fn error(&self, message: String) -> CommandParseError {
+        CommandParseError::Parameter(Located::new(self.location, message))
+    }
+
+    fn duplicate(&self) -> CommandParseError {
+        self.error(format!("duplicate extension {}", self.keyword))
+    }
+
+    fn unknown(&self) -> CommandParseError {
+        self.error(format!("unknown extension {}", self.keyword))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -364,6 +866,27 @@ enum CommandParseError {
     /// Unknown command
     #[error("Command not recognized")]
     Unknown,
+    /// Unrecognized or invalid MAIL or RCPT parameter
+    #[error("Invalid parameter - {0}")]
+    Parameter(Located<SyntaxError>),
+}
+
+impl CommandParseError {
+    fn code(&self) -> u16 {
+        match self {
+            CommandParseError::Syntax(_) | CommandParseError::Unknown => 500,
+            CommandParseError::Parameter(_) => 555,
+        }
+    }
+
+    /// Enhanced status code (RFC 3463)
+    fn status(&self) -> Status {
+        match self {
+            CommandParseError::Syntax(_) => Status::SYNTAX_ERROR,
+            CommandParseError::Unknown => Status::INVALID_COMMAND,
+            CommandParseError::Parameter(_) => Status::INVALID_ARGUMENTS,
+        }
+    }
 }
 
 impl<'a> Command<'a> {
@@ -385,6 +908,8 @@ impl<'a> Command<'a> {
             Command::parse_rcpt(&mut line)?
         } else if command.eq_ignore_ascii_case("DATA") {
             Command::Data
+        } else if command.eq_ignore_ascii_case("BDAT") {
+            Command::parse_bdat(&mut line)?
         } else if command.eq_ignore_ascii_case("RSET") {
             Command::Reset
         } else if command.eq_ignore_ascii_case("VRFY") {
@@ -397,6 +922,10 @@ impl<'a> Command<'a> {
             Command::parse_noop(&mut line)?
         } else if command.eq_ignore_ascii_case("QUIT") {
             Command::Quit
+        } else if command.eq_ignore_ascii_case("STARTTLS") {
+            Command::StartTls
+        } else if command.eq_ignore_ascii_case("AUTH") {
+            Command::parse_auth(&mut line)?
         } else {
             return Err(CommandParseError::Unknown);
         };
@@ -426,39 +955,86 @@ impl<'a> Command<'a> {
         let from = syntax::reverse_path(line)?;
 
         let mut size = None;
-
-        while line.expect(b" ").is_ok() {
-            let location = line.location();
-            let (extension, value) = syntax::parameter(line)?;
-
-            match_ignore_ascii_case! { extension;
-                "SIZE" => {
-                    if size.is_some() {
-                        return Err(Located::new(
-                            location, format!("duplicate extension {extension}")).into())
+        let mut body = None;
+        let mut smtputf8 = false;
+        let mut ret = None;
+        let mut envelope_id = None;
+
+        while let Some(parameter) = Parameter::next(line)? {
+            match_ignore_ascii_case! { parameter.keyword;
+                "SIZE" => parameter.set_once(&mut size, str::parse)?,
+                "BODY" => parameter.set_once(&mut body, str::parse)?,
+                "SMTPUTF8" => {
+                    if smtputf8 {
+                        return Err(parameter.duplicate());
                     }
 
-                    match value.parse::<usize>() {
-                        Ok(value) => size = Some(value),
-                        Err(err) => return Err(Located::new(
-                            location, err.to_string()).into()),
+                    if parameter.value.is_some() {
+                        return Err(parameter.error(
+                            format!("unexpected value for {}", parameter.keyword)));
                     }
+
+                    smtputf8 = true;
                 }
-                _ => return Err(Located::new(
-                    location, format!("unknown extension {extension}")).into()),
+                "RET" => parameter.set_once(&mut ret, str::parse)?,
+                "ENVID" => parameter.set_once(&mut envelope_id, dsn::xtext)?,
+                _ => return Err(parameter.unknown()),
             }
         }
 
-        Ok(Command::Mail(Mail { from, size }))
+        Ok(Command::Mail(Mail { from, size, body, smtputf8, ret, envelope_id }))
     }
 
     fn parse_rcpt(line: &mut Buffer<'a>) -> Result<Self, CommandParseError> {
         line.expect_caseless(b" TO:")?;
         let to = syntax::forward_path(line)?;
 
-        // TODO: extensions
+        let mut dsn = dsn::Recipient::default();
+
+        while let Some(parameter) = Parameter::next(line)? {
+            match_ignore_ascii_case! { parameter.keyword;
+                "NOTIFY" => parameter.set_once(&mut dsn.notify, str::parse)?,
+                "ORCPT" => parameter.set_once(&mut dsn.original, str::parse)?,
+                _ => return Err(parameter.unknown()),
+            }
+        }
+
+        Ok(Command::Recipient(Recipient { to, dsn }))
+    }
 
-        Ok(Command::Recipient(Recipient { to }))
+    fn parse_bdat(line: &mut Buffer<'a>) -> Result<Self, CommandParseError> {
+        // bdat-cmd   = "BDAT" SP chunk-size [ SP end-marker ] CR LF
+        // chunk-size = 1*DIGIT
+        // end-marker = "LAST"
+        line.expect(b" ")?;
+
+        let location = line.location();
+        let size = line.take_while(|c, _| c.is_ascii_digit());
+        let size = match std::str::from_utf8(size).unwrap().parse::<usize>() {
+            Ok(size) => size,
+            Err(err) => return Err(Located::new(location, err.to_string()).into()),
+        };
+
+        let last = match line.expect(b" ") {
+            Ok(_) => {
+                line.expect_caseless(b"LAST")?;
+                true
+            }
+            Err(_) => false,
+        };
+
+        Ok(Command::BinaryData(BinaryData { size, last }))
+    }
+
+    fn parse_auth(line: &mut Buffer<'a>) -> Result<Self, CommandParseError> {
+        // auth-command = "AUTH" SP sasl-mech [SP initial-response]
+        line.expect(b" ")?;
+        let mechanism = crate::syntax::atom(line)?;
+        let initial_response = match line.expect(b" ") {
+            Ok(_) => Some(crate::syntax::atom(line)?),
+            Err(_) => None,
+        };
+        Ok(Command::Auth(Auth { mechanism, initial_response }))
     }
 
     fn parse_vrfy(line: &mut Buffer<'a>) -> Result<Self, CommandParseError> {
@@ -486,3 +1062,122 @@ impl<'a> Command<'a> {
         Ok(Command::Noop)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::{smtp::dsn::{Notify, Return}, storage::Storage};
+    use super::*;
+
+    /// Send a single line and return response to it, if there is one
+    async fn send(smtp: &mut Connection, line: &str) -> Option<String> {
+        smtp.buffer().extend_from_slice(line.as_bytes());
+        smtp.buffer().extend_from_slice(b"\r\n");
+        smtp.line(false).await.map(|response| String::from_utf8(response.data.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn dsn_parameters() {
+        let config = config::Smtp { delivery_reports: true, ..Default::default() };
+        let listener = config::Listener {
+            port: 2525,
+            mode: ListenerMode::Plain,
+            parsing: Parsing::Strict,
+        };
+        let state = crate::state::State::new(Storage::Memory);
+        let address = (Ipv4Addr::LOCALHOST, 2525).into();
+        let mut smtp = Connection::new(&config, listener, state.clone(), address, address);
+
+        let ehlo = send(&mut smtp, "EHLO client.example.com").await.unwrap();
+        assert!(ehlo.contains("\r\n250-DSN\r\n"));
+
+        let lines = [
+            ("MAIL FROM:<sender@example.com> RET=HDRS ENVID=QQ+2B314159", "250 2.1.0 Sender OK\r\n"),
+            ("RCPT TO:<a@example.com> NOTIFY=SUCCESS ORCPT=rfc822;a+2Bx@example.com",
+                "250 2.1.5 Recipient OK\r\n"),
+            ("RCPT TO:<b@example.com> NOTIFY=NEVER", "250 2.1.5 Recipient OK\r\n"),
+            ("RCPT TO:<c@example.com>", "250 2.1.5 Recipient OK\r\n"),
+            ("DATA", "354 Start mail input; end with <CRLF>.<CRLF>\r\n"),
+        ];
+
+        for (line, expected) in lines {
+            assert_eq!(send(&mut smtp, line).await.as_deref(), Some(expected), "{line}");
+        }
+
+        for line in [
+            "From: sender@example.com",
+            "Date: Fri, 16 Oct 2026 10:00:00 +0000",
+            "Message-ID: <dsn@example.com>",
+            "",
+            "Hello",
+        ] {
+            assert_eq!(send(&mut smtp, line).await, None);
+        }
+
+        assert_eq!(send(&mut smtp, ".").await.as_deref(), Some("250 2.6.0 Message accepted\r\n"));
+
+        let messages = state.messages().await;
+
+        let dsn = messages["dsn@example.com"].envelope.dsn.clone().unwrap();
+        assert_eq!(dsn.ret, Some(Return::Headers));
+        assert_eq!(dsn.envelope_id.as_deref(), Some("QQ+2B314159"));
+        assert_eq!(dsn.recipients.len(), 3);
+        assert_eq!(dsn.recipients[0].notify,
+            Some(Notify { success: true, failure: false, delay: false }));
+        let original = dsn.recipients[0].original.as_ref().unwrap();
+        assert_eq!((&*original.address_type, &*original.address), ("rfc822", "a+2Bx@example.com"));
+        assert_eq!(dsn.recipients[1].notify, Some(Notify::default()));
+        assert!(dsn.recipients[2].notify.is_none() && dsn.recipients[2].original.is_none());
+
+        let report = &messages["dsn.dsn@example.com"];
+        assert_eq!(report.envelope.to[0].to_string(), "sender@example.com");
+        assert!(report.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn non_ascii_sasl_response() {
+        let config = config::Smtp::default();
+        let state = crate::state::State::new(Storage::Memory);
+        let address = (Ipv4Addr::LOCALHOST, 2525).into();
+        let mut smtp = Connection::new(&config, config.listeners[0], state, address, address);
+
+        send(&mut smtp, "EHLO client.example.com").await;
+
+        assert_eq!(send(&mut smtp, "AUTH LOGIN").await.as_deref(), Some("334 VXNlcm5hbWU6\r\n"));
+        assert_eq!(send(&mut smtp, "dXNlcg==\u{e9}").await.as_deref(),
+            Some("501 5.5.2 Cannot decode base64 response\r\n"));
+        assert_eq!(send(&mut smtp, "NOOP").await.as_deref(), Some("250 2.0.0 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn invalid_dsn_parameters() {
+        let config = config::Smtp::default();
+        let state = crate::state::State::new(Storage::Memory);
+        let address = (Ipv4Addr::LOCALHOST, 2525).into();
+        let mut smtp = Connection::new(&config, config.listeners[0], state, address, address);
+
+        send(&mut smtp, "EHLO client.example.com").await;
+
+        for line in [
+            "MAIL FROM:<sender@example.com> RET=ALL",
+            "MAIL FROM:<sender@example.com> RET=FULL RET=HDRS",
+            "MAIL FROM:<sender@example.com> ENVID=a+0",
+        ] {
+            let response = send(&mut smtp, line).await.unwrap();
+            assert!(response.starts_with("555 5.5.4 "), "{line}: {response}");
+        }
+
+        send(&mut smtp, "MAIL FROM:<sender@example.com>").await;
+
+        for line in [
+            "RCPT TO:<a@example.com> NOTIFY=NEVER,SUCCESS",
+            "RCPT TO:<a@example.com> NOTIFY=SUCCESS,SUCCESS",
+            "RCPT TO:<a@example.com> ORCPT=a@example.com",
+            "RCPT TO:<a@example.com> ORCPT=rfc822;a+2@example.com",
+        ] {
+            let response = send(&mut smtp, line).await.unwrap();
+            assert!(response.starts_with("555 5.5.4 "), "{line}: {response}");
+        }
+    }
+}
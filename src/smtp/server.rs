@@ -5,30 +5,58 @@
 //! SMTP server
 
 use anyhow::{Context, Result};
-use std::net::{Ipv6Addr, SocketAddr};
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+use memchr::memmem;
+use std::{mem, net::{Ipv6Addr, SocketAddr}};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+use futures_util::future::try_join_all;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
 
-use crate::{state::StateRef, util, config};
-use super::proto::Connection;
+use crate::{state::StateRef, util, config::{self, ListenerMode}};
+use super::{proto::Connection, tls};
 
 pub async fn start(config: config::Smtp, state: StateRef) -> Result<()> {
-    // IPv6 TCP listener on port 587 (per RFC 6409)
-    let listener = TcpListener::bind((Ipv6Addr::UNSPECIFIED, config.port))
+    let acceptor = if config.listeners.iter().any(|listener| listener.mode != ListenerMode::Plain) {
+        Some(tls::acceptor(&config.tls)?)
+    } else {
+        None
+    };
+
+    try_join_all(config.listeners.iter().map(|&listener| {
+        let acceptor = match listener.mode {
+            ListenerMode::Plain => None,
+            _ => acceptor.clone(),
+        };
+        listen(config.clone(), listener, state.clone(), acceptor)
+    })).await?;
+
+    Ok(())
+}
+
+/// Accept connections on a single listener
+async fn listen(
+    config: config::Smtp,
+    listener: config::Listener,
+    state: StateRef,
+    acceptor: Option<TlsAcceptor>,
+) -> Result<()> {
+    // IPv6 TCP listener, on port 587 by default (per RFC 6409)
+    let socket = TcpListener::bind((Ipv6Addr::UNSPECIFIED, listener.port))
         .await
-        .with_context(|| format!("could not bind TCP socket on [{}]:{}", Ipv6Addr::UNSPECIFIED, config.port))?;
+        .with_context(|| format!("could not bind TCP socket on [{}]:{}", Ipv6Addr::UNSPECIFIED, listener.port))?;
 
-    log::info!("Started SMTP server on {}", listener.local_addr()?);
+    log::info!("Started SMTP server on {} ({:?})", socket.local_addr()?, listener.mode);
 
     loop {
-        let (socket, addr) = listener.accept()
+        let (socket, addr) = socket.accept()
             .await
             .context("could not accept connection")?;
 
         let config = config.clone();
         let state = state.clone();
+        let acceptor = acceptor.clone();
 
         tokio::spawn(async move {
-            if let Err(err) = handle_client(config, state, socket, addr).await {
+            if let Err(err) = handle_client(config, listener, state, acceptor, socket, addr).await {
                 log::error!("error serving {addr}: {err:?}");
             }
         });
@@ -38,88 +66,161 @@ pub async fn start(config: config::Smtp, state: StateRef) -> Result<()> {
 /// Handle one SMTP connection
 async fn handle_client(
     config: config::Smtp,
+    listener: config::Listener,
     state: StateRef,
+    acceptor: Option<TlsAcceptor>,
     mut socket: TcpStream,
     addr: SocketAddr,
 ) -> Result<()> {
-    let mut smtp = Connection::new(&config, state, socket.local_addr()?, addr);
+    let mut smtp = Connection::new(&config, listener, state, socket.local_addr()?, addr);
 
-    {
-        let response = smtp.connect();
-        socket.write_all(response.data).await?;
+    if listener.mode == ListenerMode::ImplicitTls {
+        let mut socket = accept_tls(acceptor, socket).await?;
 
-        if response.close_connection {
-            return Ok(());
+        if greet(&mut smtp, &mut socket).await? {
+            serve(&mut smtp, &mut socket).await?;
         }
+
+        return Ok(());
     }
 
-    if let Err(err) = handle_commands(&mut smtp, &mut socket).await {
-        let _ = socket.write_all(smtp.close().data).await;
-        return Err(err);
+    if !greet(&mut smtp, &mut socket).await? {
+        return Ok(());
+    }
+
+    if let Outcome::StartTls = serve(&mut smtp, &mut socket).await? {
+        let mut socket = accept_tls(acceptor, socket).await?;
+        smtp.tls_started();
+        serve(&mut smtp, &mut socket).await?;
     }
 
     Ok(())
 }
 
-async fn handle_commands(smtp: &mut Connection, socket: &mut TcpStream) -> Result<()> {
-    loop {
-        let overflow = read_line(socket, smtp.buffer()).await?;
-        let response = smtp.line(overflow).await;
+async fn accept_tls(acceptor: Option<TlsAcceptor>, socket: TcpStream)
+-> Result<TlsStream<TcpStream>> {
+    let acceptor = acceptor.context("TLS is not available on this listener")?;
+    acceptor.accept(socket).await.context("TLS negotiation failed")
+}
 
-        if let Some(response) = response {
-            log::trace!("<< {}", util::maybe_ascii(response.data));
-            socket.write_all(response.data).await?;
-            socket.flush().await?;
+/// Send greeting to the client
+///
+/// Returns boolean indicating whether the connection should remain open.
+async fn greet<S>(smtp: &mut Connection, socket: &mut S) -> Result<bool>
+where
+    S: AsyncWrite + Unpin,
+{
+    let response = smtp.connect();
+    socket.write_all(response.data).await?;
+    Ok(!response.close_connection)
+}
 
-            if response.close_connection {
-                break;
-            }
+/// Handle commands, closing connection if an error occurs
+async fn serve<S>(smtp: &mut Connection, socket: &mut S) -> Result<Outcome>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match handle_commands(smtp, socket).await {
+        Ok(outcome) => Ok(outcome),
+        Err(err) => {
+            let _ = socket.write_all(smtp.close().data).await;
+            Err(err)
         }
     }
+}
 
-    Ok(())
+/// Reason for which [`handle_commands`] has returned
+enum Outcome {
+    /// Connection should be closed
+    Close,
+    /// TLS negotiation should begin
+    StartTls,
 }
 
-/// Read single line into a line buffer
-///
-/// Returns boolean indicating whether a buffer overflow has occurred.
-async fn read_line(socket: &mut TcpStream, line: &mut Vec<u8>)
--> Result<bool> {
+async fn handle_commands<S>(smtp: &mut Connection, socket: &mut S) -> Result<Outcome>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Data received from the client but not yet processed
+    let mut input = Vec::with_capacity(4096);
+    // Responses not yet sent to the client
+    let mut output = Vec::new();
+    // Has current line exceeded its buffer?
     let mut overflow = false;
-    let mut offset = 0;
 
-    'outer: loop {
-        socket.read_buf(line).await?;
+    loop {
+        // Process every complete command already received before sending
+        // responses, so that pipelined commands (RFC 2920) are answered
+        // together. Octets of a BDAT chunk (RFC 3030) are passed on as they
+        // arrive, as they need not form lines.
+        let available = match smtp.chunk_remaining() {
+            Some(remaining) => Some(remaining.min(input.len())).filter(|&length| length > 0),
+            None => memmem::find(&input, b"\r\n").map(|end| end + 2),
+        };
+
+        let length = match available {
+            Some(length) => length,
+            None => {
+                if smtp.chunk_remaining().is_none() {
+                    // Keep a trailing CR, as it may begin a CRLF.
+                    let length = input.len() - usize::from(input.ends_with(b"\r"));
+                    append(smtp.buffer(), &input[..length], &mut overflow);
+                    input.drain(..length);
+                }
 
-        while offset < line.len() {
-            match line[offset..].iter().position(|&c| c == b'\r') {
-                None => offset = line.len(),
-                Some(o) => {
-                    offset += o;
+                if !output.is_empty() {
+                    log::trace!("<< {}", util::maybe_ascii(&output));
+                    socket.write_all(&output).await?;
+                    socket.flush().await?;
+                    output.clear();
+                }
 
-                    if line[offset..].starts_with(b"\r\n") {
-                        break 'outer;
-                    }
+                if socket.read_buf(&mut input).await? == 0 {
+                    return Ok(Outcome::Close);
                 }
-            }
 
-            if line.ends_with(b"\r") {
-                offset -= 1;
+                continue;
             }
-        }
+        };
 
-        if offset >= line.capacity() {
-            overflow = true;
-            offset = 0;
+        let response = if smtp.chunk_remaining().is_some() {
+            smtp.chunk(&input[..length]).await
+        } else {
+            append(smtp.buffer(), &input[..length], &mut overflow);
+            smtp.line(mem::take(&mut overflow)).await
+        };
 
-            if line.ends_with(b"\r") {
-                line.clear();
-                line.push(b'\r');
-            } else {
-                line.clear();
+        input.drain(..length);
+
+        if let Some(response) = response {
+            output.extend_from_slice(response.data);
+
+            // Any commands pipelined after STARTTLS are discarded, as they
+            // were not protected by TLS (RFC 3207 section 4.2).
+            if response.close_connection || response.start_tls {
+                log::trace!("<< {}", util::maybe_ascii(&output));
+                socket.write_all(&output).await?;
+                socket.flush().await?;
+
+                return Ok(if response.close_connection {
+                    Outcome::Close
+                } else {
+                    Outcome::StartTls
+                });
             }
         }
     }
+}
 
-    Ok(overflow)
+/// Append part of a line to a line buffer
+///
+/// If the line does not fit in the buffer's capacity the buffer is cleared,
+/// `overflow` is set, and the rest of the line is discarded.
+fn append(buffer: &mut Vec<u8>, data: &[u8], overflow: &mut bool) {
+    if *overflow || buffer.len() + data.len() > buffer.capacity() {
+        *overflow = true;
+        buffer.clear();
+    } else {
+        buffer.extend_from_slice(data);
+    }
 }
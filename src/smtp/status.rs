@@ -0,0 +1,48 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Implementation of [RFC 3463](https://datatracker.ietf.org/doc/html/rfc3463):
+//! Enhanced Mail System Status Codes
+
+use std::fmt;
+
+/// Enhanced status code, sent along with reply codes when ENHANCEDSTATUSCODES
+/// (RFC 2034) is in use
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Status {
+    /// 2 for success, 4 for persistent transient failure, and 5 for permanent
+    /// failure
+    pub class: u8,
+    pub subject: u16,
+    pub detail: u16,
+}
+
+impl Status {
+    /// 2.0.0 Other undefined status
+    pub const OK: Status = Status::new(2, 0, 0);
+    /// 4.3.0 Other or undefined mail system status
+    pub const SYSTEM_ERROR: Status = Status::new(4, 3, 0);
+    /// 5.3.0 Other or undefined mail system status
+    pub const MAIL_SYSTEM_ERROR: Status = Status::new(5, 3, 0);
+    /// 5.5.1 Invalid command
+    pub const INVALID_COMMAND: Status = Status::new(5, 5, 1);
+    /// 5.5.2 Syntax error
+    pub const SYNTAX_ERROR: Status = Status::new(5, 5, 2);
+    /// 5.5.4 Invalid command arguments
+    pub const INVALID_ARGUMENTS: Status = Status::new(5, 5, 4);
+    /// 5.6.0 Other or undefined media error
+    pub const MEDIA_ERROR: Status = Status::new(5, 6, 0);
+    /// 5.6.1 Media not supported
+    pub const MEDIA_NOT_SUPPORTED: Status = Status::new(5, 6, 1);
+
+    pub const fn new(class: u8, subject: u16, detail: u16) -> Status {
+        Status { class, subject, detail }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.class, self.subject, self.detail)
+    }
+}
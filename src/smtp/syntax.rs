@@ -2,6 +2,7 @@
 // Licensed under the MIT license. See LICENSE file in the project root for
 // full license text.
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
 use std::{fmt, net::{IpAddr, Ipv4Addr, Ipv6Addr}, str};
 
 use crate::syntax::*;
@@ -11,6 +12,7 @@ pub enum ReversePathRef<'a> {
     Mailbox(MailboxRef<'a>),
 }
 
+#[derive(Clone, Debug)]
 pub enum ReversePath {
     Null,
     Mailbox(Mailbox),
@@ -34,6 +36,35 @@ impl ReversePath {
     }
 }
 
+impl fmt::Display for ReversePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReversePath::Null => f.write_str("<>"),
+            ReversePath::Mailbox(mb) => mb.fmt(f),
+        }
+    }
+}
+
+impl Serialize for ReversePath {
+    fn serialize<S: Serializer>(&self, se: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ReversePath::Null => se.serialize_none(),
+            ReversePath::Mailbox(mb) => se.collect_str(mb),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReversePath {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        match Option::<String>::deserialize(de)? {
+            None => Ok(ReversePath::Null),
+            Some(path) => parse_str(&format!("<{path}>"), reverse_path)
+                .map(|path| path.to_owned())
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
 pub fn reverse_path<'a>(buf: &mut Buffer<'a>) -> Result<ReversePathRef<'a>> {
     // Reverse-path = Path / "<>"
     if buf.starts_with(b"<>") {
@@ -49,6 +80,7 @@ pub enum ForwardPathRef<'a> {
     Mailbox(MailboxRef<'a>),
 }
 
+#[derive(Clone, Debug)]
 pub enum ForwardPath {
     Postmaster(Option<String>),
     Mailbox(Mailbox),
@@ -72,6 +104,31 @@ impl ForwardPath {
     }
 }
 
+impl fmt::Display for ForwardPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForwardPath::Postmaster(None) => f.write_str("postmaster"),
+            ForwardPath::Postmaster(Some(domain)) => write!(f, "postmaster@{domain}"),
+            ForwardPath::Mailbox(mb) => mb.fmt(f),
+        }
+    }
+}
+
+impl Serialize for ForwardPath {
+    fn serialize<S: Serializer>(&self, se: S) -> Result<S::Ok, S::Error> {
+        se.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ForwardPath {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let path = String::deserialize(de)?;
+        parse_str(&format!("<{path}>"), forward_path)
+            .map(|path| path.to_owned())
+            .map_err(D::Error::custom)
+    }
+}
+
 pub fn forward_path<'a>(buf: &mut Buffer<'a>) -> Result<ForwardPathRef<'a>> {
     if buf.expect_caseless(b"<postmaster>").is_ok() {
         return Ok(ForwardPathRef::Postmaster(None));
@@ -119,26 +176,29 @@ pub fn path<'a>(buf: &mut Buffer<'a>) -> Result<MailboxRef<'a>> {
     })
 }
 
-pub fn parameter<'a>(buf: &mut Buffer<'a>) -> Result<(&'a str, &'a str)> {
+pub fn parameter<'a>(buf: &mut Buffer<'a>) -> Result<(&'a str, Option<&'a str>)> {
     // Mail-parameters = esmtp-param *(SP esmtp-param)
     // Rcpt-parameters = esmtp-param *(SP esmtp-param)
     // esmtp-param     = esmtp-keyword ["=" esmtp-value]
     // esmtp-keyword   = (ALPHA / DIGIT) *(ALPHA / DIGIT / "-")
     // esmtp-value     = 1*(%d33-60 / %d62-126)
+    // esmtp-value     =/ UTF8-non-ascii
     buf.atomic(|buf| {
         let keyword = buf.take_while(|c, inx| c.is_ascii_alphanumeric() || c == b'-' && inx > 0);
         if keyword.is_empty() {
             return buf.error("expected a keyword");
         }
 
-        buf.expect(b"=")?;
+        if buf.expect(b"=").is_err() {
+            return Ok((str::from_utf8(keyword).unwrap(), None));
+        }
 
-        let value = buf.take_while(|c, _| matches!(c, 33..=60 | 62..=126));
+        let value = buf.take_while_utf8(|c| matches!(c, 33..=60 | 62..=126));
         if value.is_empty() {
             return buf.error("expected a value");
         }
 
-        Ok((str::from_utf8(keyword).unwrap(), str::from_utf8(value).unwrap()))
+        Ok((str::from_utf8(keyword).unwrap(), Some(str::from_utf8(value).unwrap())))
     })
 }
 
@@ -152,6 +212,7 @@ pub enum DomainRefOrAddr<'a> {
     Addr(IpAddr),
 }
 
+#[derive(Clone, Debug)]
 pub enum DomainOrAddr {
     Domain(String),
     Addr(IpAddr),
@@ -179,7 +240,8 @@ impl fmt::Display for DomainRefOrAddr<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             DomainRefOrAddr::Domain(domain) => domain.fmt(f),
-            DomainRefOrAddr::Addr(addr) => addr.fmt(f),
+            DomainRefOrAddr::Addr(IpAddr::V4(addr)) => write!(f, "[{addr}]"),
+            DomainRefOrAddr::Addr(IpAddr::V6(addr)) => write!(f, "[IPv6:{addr}]"),
         }
     }
 }
@@ -190,6 +252,21 @@ impl fmt::Display for DomainOrAddr {
     }
 }
 
+impl Serialize for DomainOrAddr {
+    fn serialize<S: Serializer>(&self, se: S) -> Result<S::Ok, S::Error> {
+        se.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DomainOrAddr {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(de)?;
+        parse_str(&value, domain_or_address)
+            .map(|value| value.to_owned())
+            .map_err(D::Error::custom)
+    }
+}
+
 pub fn domain_or_address<'a>(buf: &mut Buffer<'a>) -> Result<DomainRefOrAddr<'a>> {
     if buf.starts_with(b"[") {
         address_literal(buf).map(DomainRefOrAddr::Addr)
@@ -204,13 +281,15 @@ pub fn domain<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
         loop {
             // sub-domain = Let-dig [Ldh-str]
             // Let-dig    = ALPHA / DIGIT
-            if !buf[0].is_ascii_alphanumeric() {
-                return buf.error("expected letter or digit");
+            // sub-domain =/ U-label
+            match buf.first() {
+                Some(ch) if ch.is_ascii_alphanumeric() => buf.advance(1),
+                Some(0x80..) => utf8_non_ascii(buf)?,
+                _ => return buf.error("expected letter or digit"),
             }
-            buf.advance(1);
 
             // Ldh-str = *( ALPHA / DIGIT / "-" ) Let-dig
-            let ldh = buf.take_while(|ch, _| ch.is_ascii_alphabetic() || ch == b'-');
+            let ldh = buf.take_while_utf8(|ch| ch.is_ascii_alphanumeric() || ch == b'-');
 
             if ldh.ends_with(b"-") {
                 return buf.error("expected letter or digit following '-'");
@@ -347,6 +426,7 @@ pub struct MailboxRef<'a> {
     pub location: DomainRefOrAddr<'a>,
 }
 
+#[derive(Clone, Debug)]
 pub struct Mailbox {
     pub local: String,
     pub location: DomainOrAddr,
@@ -370,6 +450,12 @@ impl Mailbox {
     }
 }
 
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.local, self.location)
+    }
+}
+
 pub fn mailbox<'a>(buf: &mut Buffer<'a>) -> Result<MailboxRef<'a>> {
     // Mailbox    = Local-part "@" ( Domain / address-literal )
     // Local-part = Dot-string / Quoted-string
@@ -403,6 +489,8 @@ pub fn quoted_string<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
             match buf[0] {
                 // qtextSMTP = %d32-33 / %d35-91 / %d93-126
                 32..=33 | 35..=91 | 93..=126 => buf.advance(1),
+                // qtextSMTP =/ UTF8-non-ascii
+                0x80.. => utf8_non_ascii(buf)?,
                 // quoted-pairSMTP = %d92 %d32-126
                 92 if buf.len() > 1 => match buf[1] {
                     32..=126 => buf.advance(2),
@@ -423,3 +511,12 @@ pub fn string<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
     // String = Atom / Quoted-string
     atom(buf).or_else(|_| quoted_string(buf))
 }
+
+/// Parse entirety of `value` using `parser`
+fn parse_str<'a, T>(value: &'a str, parser: impl FnOnce(&mut Buffer<'a>) -> Result<T>)
+-> Result<T> {
+    let mut buf = Buffer::new(value.as_bytes());
+    let value = parser(&mut buf)?;
+    buf.expect_empty()?;
+    Ok(value)
+}
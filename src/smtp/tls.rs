@@ -0,0 +1,72 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! TLS setup
+
+use anyhow::{bail, Context, Result};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::{TlsAcceptor, rustls::{Certificate, PrivateKey, ServerConfig}};
+
+use crate::config;
+
+/// Create a TLS acceptor from configuration
+pub fn acceptor(config: &config::Tls) -> Result<TlsAcceptor> {
+    let (certificates, key) = match (&config.certificate, &config.key) {
+        (Some(certificate), Some(key)) => (load_certificates(certificate)?, load_key(key)?),
+        (None, None) => self_signed()?,
+        _ => bail!("both certificate and key must be specified"),
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .context("invalid certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)
+        .with_context(|| format!("could not open {}", path.display()))?);
+
+    let certificates = rustls_pemfile::certs(&mut reader)
+        .with_context(|| format!("could not read certificates from {}", path.display()))?;
+
+    if certificates.is_empty() {
+        bail!("no certificates found in {}", path.display());
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)
+        .with_context(|| format!("could not open {}", path.display()))?);
+
+    loop {
+        let item = rustls_pemfile::read_one(&mut reader)
+            .with_context(|| format!("could not read private key from {}", path.display()))?;
+
+        match item {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => bail!("no private key found in {}", path.display()),
+        }
+    }
+}
+
+fn self_signed() -> Result<(Vec<Certificate>, PrivateKey)> {
+    log::info!("Generating self-signed TLS certificate");
+
+    let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()])
+        .context("could not generate self-signed certificate")?;
+
+    Ok((
+        vec![Certificate(certificate.serialize_der()?)],
+        PrivateKey(certificate.serialize_private_key_der()),
+    ))
+}
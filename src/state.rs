@@ -2,40 +2,139 @@
 // Licensed under the MIT license. See LICENSE file in the project root for
 // full license text.
 
-use std::{collections::{HashMap, hash_map::Entry}, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, sync::Arc, net::SocketAddr};
 use thiserror::Error;
 use time::{OffsetDateTime, UtcOffset};
 use tokio::sync::{RwLock, broadcast};
 
-use crate::{mail::{self, Mailbox, AddressOrGroup}, syntax::{SyntaxError, Located, Location}, mime};
+use crate::{
+    config::Parsing,
+    mail::{self, Mailbox, AddressOrGroup, HeaderField, Path},
+    mime,
+    smtp::{dsn::Dsn, DomainOrAddr, ForwardPath, ReversePath, Status},
+    storage::Storage,
+    syntax::{SyntaxError, Located, Location},
+    util,
+};
 
 pub struct State {
+    storage: Storage,
     messages: RwLock<HashMap<String, Arc<Message>>>,
-    on_message: broadcast::Sender<Arc<Message>>,
+    events: broadcast::Sender<Event>,
 }
 
 pub type StateRef = Arc<State>;
 
+/// Change to the list of messages
+#[derive(Clone)]
+pub enum Event {
+    /// A new message was received
+    Received(Arc<Message>),
+    /// Messages were deleted
+    Deleted(Vec<String>),
+}
+
 pub struct Message {
     pub id: String,
+    pub envelope: Envelope,
+    /// All header fields, in order in which they appear
+    pub headers: Vec<HeaderField>,
     pub date: OffsetDateTime,
     pub from: Vec<Mailbox>,
+    pub sender: Option<Mailbox>,
+    pub reply_to: Vec<AddressOrGroup>,
     pub subject: Option<String>,
     pub to: Vec<AddressOrGroup>,
+    pub cc: Vec<AddressOrGroup>,
+    pub bcc: Vec<AddressOrGroup>,
+    /// IDs of messages to which this one is a reply
+    pub in_reply_to: Vec<String>,
+    /// IDs of messages in the thread this one belongs to
+    pub references: Vec<String>,
+    pub keywords: Vec<String>,
+    pub comments: Vec<String>,
+    /// Trace blocks, most recent first
+    pub trace: Vec<Trace>,
     pub body: MessageBody,
     pub errors: Vec<Located<String>>,
+    /// Message exactly as it was received
+    pub raw: Vec<u8>,
+}
+
+/// SMTP envelope under which a message was received
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Envelope {
+    /// Name client introduced itself with in EHLO/HELO
+    pub client: Option<DomainOrAddr>,
+    /// Address of the client
+    #[serde(with = "util::as_string")]
+    pub remote: SocketAddr,
+    /// Was this message received over an encrypted connection?
+    pub tls: bool,
+    /// Name of the user client has authenticated as
+    pub user: Option<String>,
+    /// Reverse path (MAIL FROM)
+    pub from: ReversePath,
+    /// Forward paths (RCPT TO)
+    pub to: Vec<ForwardPath>,
+    /// Time at which the message was received
+    ///
+    /// Messages stored before this was recorded are dated at the time they
+    /// were loaded, which is saved the next time storage index is rewritten.
+    #[serde(default = "OffsetDateTime::now_utc", with = "time::serde::timestamp")]
+    pub received: OffsetDateTime,
+    /// Delivery status notification parameters, if client provided any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dsn: Option<Dsn>,
+}
+
+/// Trace information added by agents which relayed or resent a message
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    /// Address to which delivery errors should be sent (Return-Path)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_path: Option<Path>,
+    /// Hops this message took, most recent first
+    pub received: Vec<Received>,
+    /// Resent-* blocks, most recent first
+    pub resent: Vec<Resent>,
+}
+
+/// Single Received header
+#[derive(Clone, Debug, Serialize)]
+pub struct Received {
+    /// Text preceding the date, describing the hop
+    pub text: String,
+    #[serde(with = "time::serde::timestamp")]
+    pub date: OffsetDateTime,
+}
+
+/// Information about a message having been resent
+#[derive(Clone, Debug, Serialize)]
+pub struct Resent {
+    #[serde(with = "time::serde::timestamp::option")]
+    pub date: Option<OffsetDateTime>,
+    pub from: Vec<Mailbox>,
+    pub sender: Option<Mailbox>,
+    pub to: Vec<AddressOrGroup>,
+    pub cc: Vec<AddressOrGroup>,
+    pub bcc: Vec<AddressOrGroup>,
+    pub id: Option<String>,
 }
 
 pub enum MessageBody {
     Unknown(String),
-    Mime(mime::Entity),
+    Mime(Box<mime::Entity>),
 }
 
 impl State {
-    pub fn new() -> StateRef {
+    pub fn new(storage: Storage) -> StateRef {
         Arc::new(State {
+            storage,
             messages: RwLock::new(HashMap::default()),
-            on_message: broadcast::channel(16).0,
+            events: broadcast::channel(16).0,
         })
     }
 
@@ -47,50 +146,191 @@ impl State {
         self.messages.read().await.get(id).cloned()
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Message>> {
-        self.on_message.subscribe()
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Delete a message
+    ///
+    /// Returns `false` if there was no such message.
+    pub async fn delete_message(&self, id: &str) -> io::Result<bool> {
+        let mut messages = self.messages.write().await;
+
+        if !messages.contains_key(id) {
+            return Ok(false);
+        }
+
+        let ids = vec![id.to_string()];
+        self.storage.delete(&ids).await?;
+        messages.remove(id);
+        drop(messages);
+
+        let _ = self.events.send(Event::Deleted(ids));
+
+        Ok(true)
+    }
+
+    /// Delete all messages
+    pub async fn clear(&self) -> io::Result<()> {
+        let mut messages = self.messages.write().await;
+
+        let ids = messages.keys().cloned().collect::<Vec<_>>();
+        self.storage.delete(&ids).await?;
+        messages.clear();
+        drop(messages);
+
+        if !ids.is_empty() {
+            let _ = self.events.send(Event::Deleted(ids));
+        }
+
+        Ok(())
+    }
+
+    /// Load messages saved in storage
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let stored = self.storage.load().await?;
+        let mut messages = self.messages.write().await;
+
+        for stored in stored {
+            // Stored messages were already accepted, possibly by a lenient
+            // listener.
+            match Message::parse(Some(stored.id), stored.envelope, &stored.data, Parsing::Lenient) {
+                Ok(message) => {
+                    messages.insert(message.id.clone(), Arc::new(message));
+                }
+                Err(err) => log::warn!("could not load stored message: {err}"),
+            }
+        }
+
+        log::info!("Loaded {} messages from storage", messages.len());
+
+        Ok(())
+    }
+
+    pub async fn submit_message(&self, envelope: Envelope, message: &[u8], parsing: Parsing)
+    -> Result<Arc<Message>, SubmitMessageError> {
+        let parsed = Message::parse(None, envelope, message, parsing)?;
+        self.add_message(parsed, parsing).await
     }
 
-    pub async fn submit_message(&self, message: &[u8]) -> Result<(), SubmitMessageError> {
+    /// Add message to `self.messages`, save it in storage, and notify listeners
+    ///
+    /// In lenient mode a message whose ID is already taken is stored under
+    /// a new ID, made unique by adding a numeric suffix to the original one.
+    async fn add_message(&self, mut message: Message, parsing: Parsing)
+    -> Result<Arc<Message>, SubmitMessageError> {
+        let mut messages = self.messages.write().await;
+
+        if messages.contains_key(&message.id) {
+            if parsing == Parsing::Strict {
+                return Err(SubmitMessageError::DuplicateMailId);
+            }
+
+            let (left, right) = message.id.rsplit_once('@').unwrap_or((&message.id, ""));
+            let id = (1..)
+                .map(|suffix| match right {
+                    "" => format!("{left}.{suffix}"),
+                    _ => format!("{left}.{suffix}@{right}"),
+                })
+                .find(|id| !messages.contains_key(id))
+                .unwrap();
+
+            message.errors.push(Located::new(
+                Location::ZERO, format!("duplicate message ID {}, stored as {id}", message.id)));
+            message.id = id;
+        }
+
+        let message = Arc::new(message);
+        self.storage.store(&message.id, &message.envelope, &message.raw).await?;
+        messages.insert(message.id.clone(), message.clone());
+        drop(messages);
+
+        let _ = self.events.send(Event::Received(message.clone()));
+
+        Ok(message)
+    }
+}
+
+impl Message {
+    /// Parse a message
+    ///
+    /// When `id` is not provided it will be taken from the Message-ID header,
+    /// or generated if there is none.
+    ///
+    /// In lenient mode this never fails; all problems are recorded in
+    /// [`Message::errors`], and a message without a date is dated at the time
+    /// it was received.
+    fn parse(id: Option<String>, envelope: Envelope, raw: &[u8], parsing: Parsing)
+    -> Result<Message, SubmitMessageError> {
         let mut errors = Vec::new();
-        let mut collector = Errors::new(&mut errors);
+        let mut collector = Errors::new(&mut errors, parsing);
 
-        let message = mail::parse(message, &mut collector)?;
+        let message = mail::parse(raw, &mut collector)?;
 
         let body = match message.body {
-            mail::Body::Unknown(body) =>
-                MessageBody::Unknown(String::from_utf8(body.to_vec())?),
-            mail::Body::Mime(body) => MessageBody::Mime(body.parse(&mut collector)?),
+            mail::Body::Unknown(body) => match String::from_utf8(body.to_vec()) {
+                Ok(body) => MessageBody::Unknown(body),
+                Err(error) => {
+                    collector.recover(Located { at: Location::ZERO, item: error })
+                        .map_err(|error| error.item)?;
+                    MessageBody::Unknown(String::from_utf8_lossy(body).into_owned())
+                }
+            },
+            mail::Body::Mime(body) => MessageBody::Mime(Box::new(body.parse(&mut collector)?)),
+        };
+
+        let date = match message.origination_date {
+            Some(date) => date.with_offset_when_missing(UtcOffset::UTC),
+            None => envelope.received,
         };
 
-        let message = Message {
-            id: message.id.unwrap_or_else(
-                || format!("{}@local", OffsetDateTime::now_utc().unix_timestamp())),
-            date: message.origination_date.with_offset_when_missing(UtcOffset::UTC),
+        Ok(Message {
+            id: id.or(message.id).unwrap_or_else(
+                || format!("{}@local", OffsetDateTime::now_utc().unix_timestamp_nanos())),
+            envelope,
+            headers: message.headers,
+            date,
             from: message.from.iter().map(|x| x.to_owned()).collect(),
+            sender: message.sender.map(|x| x.to_owned()),
+            reply_to: message.reply_to.iter().map(|x| x.to_owned()).collect(),
             subject: message.subject,
             to: message.to.iter().map(|x| x.to_owned()).collect(),
+            cc: message.cc.iter().map(|x| x.to_owned()).collect(),
+            bcc: message.bcc.iter().map(|x| x.to_owned()).collect(),
+            in_reply_to: message.in_reply_to.iter().map(|x| x.0.into()).collect(),
+            references: message.references.iter().map(|x| x.0.into()).collect(),
+            keywords: message.keywords,
+            comments: message.comments,
+            trace: message.trace.iter().map(Trace::from).collect(),
             body,
             errors,
-        };
-
-        self.add_message(message).await
+            raw: raw.to_vec(),
+        })
     }
+}
 
-    /// Add message to `self.messages` and notify listeners
-    async fn add_message(&self, message: Message) -> Result<(), SubmitMessageError> {
-        let message = Arc::new(message);
-
-        match self.messages.write().await.entry(message.id.clone()) {
-            Entry::Occupied(_) => return Err(SubmitMessageError::DuplicateMailId),
-            Entry::Vacant(entry) => {
-                entry.insert(message.clone());
-            }
+impl From<&'_ mail::Trace<'_>> for Trace {
+    fn from(trace: &mail::Trace) -> Self {
+        Trace {
+            return_path: trace.return_path.map(|path| path.to_owned()),
+            received: trace.received.iter()
+                .map(|received| Received {
+                    text: received.text.unfold(),
+                    date: received.date.with_offset_when_missing(UtcOffset::UTC),
+                })
+                .collect(),
+            resent: trace.resending.iter()
+                .map(|info| Resent {
+                    date: info.date.map(|date| date.with_offset_when_missing(UtcOffset::UTC)),
+                    from: info.from.iter().map(|x| x.to_owned()).collect(),
+                    sender: info.sender.map(|x| x.to_owned()),
+                    to: info.to.iter().map(|x| x.to_owned()).collect(),
+                    cc: info.cc.iter().map(|x| x.to_owned()).collect(),
+                    bcc: info.bcc.iter().map(|x| x.to_owned()).collect(),
+                    id: info.id.clone(),
+                })
+                .collect(),
         }
-
-        let _ = self.on_message.send(message);
-
-        Ok(())
     }
 }
 
@@ -104,6 +344,8 @@ pub enum SubmitMessageError {
     Encoding(#[from] std::string::FromUtf8Error),
     #[error("Syntax error - {0}")]
     Mime(#[from] mime::Error),
+    #[error("Could not save message - {0}")]
+    Storage(#[from] std::io::Error),
 }
 
 impl SubmitMessageError {
@@ -112,6 +354,21 @@ impl SubmitMessageError {
             SubmitMessageError::Syntax(_) | SubmitMessageError::Encoding(_)
             | SubmitMessageError::Mime(_) => 500,
             SubmitMessageError::DuplicateMailId => 550,
+            SubmitMessageError::Storage(_) => 451,
+        }
+    }
+
+    /// Enhanced status code (RFC 3463)
+    pub fn status(&self) -> Status {
+        match self {
+            SubmitMessageError::Mime(mime::Error::UnsupportedContentType) =>
+                Status::MEDIA_NOT_SUPPORTED,
+            SubmitMessageError::Syntax(_) | SubmitMessageError::Encoding(_)
+            | SubmitMessageError::Mime(_) => Status::MEDIA_ERROR,
+            // Message is fine, but this server can't keep two messages with
+            // the same ID.
+            SubmitMessageError::DuplicateMailId => Status::MAIL_SYSTEM_ERROR,
+            SubmitMessageError::Storage(_) => Status::SYSTEM_ERROR,
         }
     }
 }
@@ -119,14 +376,16 @@ impl SubmitMessageError {
 pub struct Errors<'a> {
     offset_offset: usize,
     line_offset: usize,
+    parsing: Parsing,
     errors: &'a mut Vec<Located<String>>,
 }
 
 impl<'a> Errors<'a> {
-    pub fn new(errors: &'a mut Vec<Located<String>>) -> Self {
+    pub fn new(errors: &'a mut Vec<Located<String>>, parsing: Parsing) -> Self {
         Errors {
             offset_offset: 0,
             line_offset: 0,
+            parsing,
             errors,
         }
     }
@@ -144,13 +403,72 @@ impl<'a> Errors<'a> {
         self.errors.push(Located::new(at, error.to_string()));
     }
 
+    /// Handle an error which makes the message invalid
+    ///
+    /// In strict mode the error is returned, so that parsing fails. In lenient
+    /// mode it is recorded, and the caller should carry on with whatever it
+    /// managed to parse.
+    pub fn recover<E: ToString>(&mut self, error: Located<E>) -> Result<(), Located<E>> {
+        match self.parsing {
+            Parsing::Strict => Err(error),
+            Parsing::Lenient => {
+                self.add(error);
+                Ok(())
+            }
+        }
+    }
+
     pub fn nested(&mut self, at: Location) -> Errors {
         assert!(at.column == 1);
 
         Errors {
             offset_offset: self.offset_offset + at.offset,
             line_offset: self.line_offset + at.line - 1,
+            parsing: self.parsing,
             errors: self.errors,
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Envelope of a message from localhost, without sender or recipients
+    pub fn envelope() -> Envelope {
+        Envelope {
+            client: None,
+            remote: (std::net::Ipv4Addr::LOCALHOST, 25).into(),
+            tls: false,
+            user: None,
+            from: ReversePath::Null,
+            to: vec![],
+            received: OffsetDateTime::now_utc(),
+            dsn: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn quoted_printable_with_8bit_octets() {
+        // Such parts may be sent with BODY=8BITMIME, even though they are not
+        // valid quoted-printable.
+        let message = b"From: a@example.com\r\n\
+            Date: Fri, 16 Oct 2026 10:00:00 +0000\r\n\
+            Message-ID: <qp@example.com>\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: text/plain; charset=iso-8859-2\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\
+            \r\n\
+            \xbf\xf3\xb3w=3D\r\n";
+
+        let state = State::new(Storage::Memory);
+        assert!(state.submit_message(envelope(), message, Parsing::Strict).await.is_err());
+
+        let stored = state.submit_message(envelope(), message, Parsing::Lenient).await.unwrap();
+        assert_eq!(stored.errors.len(), 1);
+        assert!(matches!(
+            stored.body,
+            MessageBody::Mime(ref entity) if matches!(entity.data, mime::EntityData::Binary(_)),
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Persistent message storage
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, io, mem, path::PathBuf};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+
+use crate::{config, state::Envelope};
+
+/// Name of the file in which [`Disk`] keeps its index, one JSON entry per line
+const INDEX: &str = "index.jsonl";
+
+/// Backend in which received messages are saved
+pub enum Storage {
+    /// Messages are kept only in memory
+    Memory,
+    /// Messages are saved in a directory
+    Disk(Disk),
+}
+
+/// Message loaded from storage
+pub struct Stored {
+    pub id: String,
+    pub envelope: Envelope,
+    pub data: Vec<u8>,
+}
+
+/// Storage keeping raw messages as separate files in a directory, together
+/// with a JSON index of their IDs and envelopes
+///
+/// Entries are appended to the index as messages are stored, and the index is
+/// only rewritten when messages are deleted.
+pub struct Disk {
+    path: PathBuf,
+    index: Mutex<Index>,
+}
+
+struct Index {
+    entries: Vec<IndexEntry>,
+    /// Number of the next message file
+    next_number: u64,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct IndexEntry {
+    id: String,
+    /// Name of the file containing message data
+    file: String,
+    envelope: Envelope,
+}
+
+impl Storage {
+    pub async fn open(config: &config::Storage) -> Result<Storage> {
+        match config {
+            config::Storage::Memory => Ok(Storage::Memory),
+            config::Storage::Disk { path } => Disk::open(path.clone())
+                .await
+                .map(Storage::Disk)
+                .with_context(|| format!("could not open message storage in {}", path.display())),
+        }
+    }
+
+    /// Load all previously stored messages
+    pub async fn load(&self) -> Result<Vec<Stored>> {
+        match self {
+            Storage::Memory => Ok(vec![]),
+            Storage::Disk(disk) => disk.load().await,
+        }
+    }
+
+    /// Save a message
+    pub async fn store(&self, id: &str, envelope: &Envelope, data: &[u8]) -> io::Result<()> {
+        match self {
+            Storage::Memory => Ok(()),
+            Storage::Disk(disk) => disk.store(id, envelope, data).await,
+        }
+    }
+
+    /// Delete messages
+    pub async fn delete(&self, ids: &[String]) -> io::Result<()> {
+        match self {
+            Storage::Memory => Ok(()),
+            Storage::Disk(disk) => disk.delete(ids).await,
+        }
+    }
+}
+
+impl Disk {
+    async fn open(path: PathBuf) -> Result<Disk> {
+        fs::create_dir_all(&path).await?;
+
+        let data = match fs::read(path.join(INDEX)).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+
+        // A line without a terminator is an entry whose write was interrupted,
+        // and its message was never acknowledged.
+        let complete = data.iter().rposition(|&b| b == b'\n').map_or(0, |inx| inx + 1);
+        let entries = data[..complete]
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<IndexEntry>, _>>()
+            .context("corrupted index")?;
+
+        // Message IDs can contain characters which are not allowed in file
+        // names, so files are numbered instead.
+        let next_number = entries.iter()
+            .filter_map(|entry| entry.file.strip_suffix(".eml")?.parse::<u64>().ok())
+            .max()
+            .map_or(0, |number| number + 1);
+
+        let disk = Disk { path, index: Mutex::new(Index { entries, next_number }) };
+
+        if complete != data.len() {
+            log::warn!("discarding incomplete entry at the end of the index");
+            disk.save_index(&disk.index.lock().await.entries).await?;
+        }
+
+        Ok(disk)
+    }
+
+    async fn load(&self) -> Result<Vec<Stored>> {
+        let index = self.index.lock().await;
+        let mut messages = Vec::with_capacity(index.entries.len());
+
+        for entry in &index.entries {
+            let data = fs::read(self.path.join(&entry.file))
+                .await
+                .with_context(|| format!("could not read message {}", entry.id))?;
+
+            messages.push(Stored {
+                id: entry.id.clone(),
+                envelope: entry.envelope.clone(),
+                data,
+            });
+        }
+
+        Ok(messages)
+    }
+
+    async fn store(&self, id: &str, envelope: &Envelope, data: &[u8]) -> io::Result<()> {
+        let mut index = self.index.lock().await;
+
+        let file = format!("{}.eml", index.next_number);
+        index.next_number += 1;
+
+        fs::write(self.path.join(&file), data).await?;
+
+        let entry = IndexEntry {
+            id: id.into(),
+            file,
+            envelope: envelope.clone(),
+        };
+
+        if let Err(err) = self.append_index(&entry).await {
+            let _ = fs::remove_file(self.path.join(&entry.file)).await;
+            // Appending might have left a partial line, which would corrupt
+            // entries appended after it.
+            if let Err(err) = self.save_index(&index.entries).await {
+                log::error!("could not restore index: {err}");
+            }
+            return Err(err);
+        }
+
+        index.entries.push(entry);
+
+        Ok(())
+    }
+
+    async fn delete(&self, ids: &[String]) -> io::Result<()> {
+        let mut index = self.index.lock().await;
+        let ids = ids.iter().map(String::as_str).collect::<HashSet<_>>();
+
+        let kept = index.entries.iter()
+            .filter(|entry| !ids.contains(entry.id.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.save_index(&kept).await?;
+
+        let removed = mem::replace(&mut index.entries, kept);
+
+        // Messages are no longer in the index, so failing to remove their
+        // files only wastes some space.
+        for entry in removed.into_iter().filter(|entry| ids.contains(entry.id.as_str())) {
+            if let Err(err) = fs::remove_file(self.path.join(&entry.file)).await {
+                log::warn!("could not remove {}: {err}", entry.file);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a single entry at the end of index file
+    async fn append_index(&self, entry: &IndexEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(INDEX))
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+
+    /// Atomically replace index file
+    async fn save_index(&self, index: &[IndexEntry]) -> io::Result<()> {
+        let mut data = Vec::new();
+        for entry in index {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+        let temp = self.path.join(format!("{INDEX}.tmp"));
+        fs::write(&temp, data).await?;
+        fs::rename(temp, self.path.join(INDEX)).await
+    }
+}
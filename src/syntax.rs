@@ -182,6 +182,24 @@ impl<'a> Buffer<'a> {
         self.take(length)
     }
 
+    /// Like [`Buffer::take_while`], but also takes non-ASCII UTF-8 characters
+    /// (RFC 6532)
+    pub fn take_while_utf8(&mut self, mut test: impl FnMut(u8) -> bool) -> &'a [u8] {
+        let mut length = 0;
+
+        while length < self.len() {
+            if test(self.data[length]) {
+                length += 1;
+            } else if let Some(char_length) = utf8_non_ascii_length(&self.data[length..]) {
+                length += char_length;
+            } else {
+                break;
+            }
+        }
+
+        self.take(length)
+    }
+
     pub fn take_matching(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<&'a [u8]> {
         let mut cursor = *self;
         f(&mut cursor)?;
@@ -199,8 +217,15 @@ impl<'a> Buffer<'a> {
         let items = self.take_matching(|slf| {
             let mut count = 0;
 
-            while slf.maybe(T::parse).is_some() {
+            if slf.maybe(T::parse).is_some() {
                 count += 1;
+
+                while slf.maybe(|buf| {
+                    buf.expect(separator)?;
+                    T::parse(buf)
+                }).is_some() {
+                    count += 1;
+                }
             }
 
             if count < min {
@@ -347,7 +372,7 @@ pub fn is_atext(b: u8) -> bool {
 pub fn atom<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
     // atom = 1*atext
     buf.atomic(|buf| {
-        let text = buf.take_while(|b, _| is_atext(b));
+        let text = buf.take_while_utf8(is_atext);
 
         if text.is_empty() {
             buf.error("expected an atom")
@@ -360,7 +385,7 @@ pub fn atom<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
 pub fn dot_atom<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
     // dot-atom = 1*atext *("." 1*atext)
     buf.atomic(|buf| {
-        let text = buf.take_while(|b, _| b == b'.' || is_atext(b));
+        let text = buf.take_while_utf8(|b| b == b'.' || is_atext(b));
 
         if text.is_empty() {
             buf.error("expected an atom")
@@ -374,3 +399,28 @@ pub fn dot_atom<'a>(buf: &mut Buffer<'a>) -> Result<&'a str> {
 pub fn is_vchar(b: u8) -> bool {
     matches!(b, 0x21..=0x7e)
 }
+
+// ---------------------------------------------------------------- RFC 6532 ---
+
+/// Length of the non-ASCII UTF-8 character `data` begins with, if any
+fn utf8_non_ascii_length(data: &[u8]) -> Option<usize> {
+    // UTF8-non-ascii = UTF8-2 / UTF8-3 / UTF8-4
+    let length = match *data.first()? {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return None,
+    };
+
+    str::from_utf8(data.get(..length)?).ok().map(|_| length)
+}
+
+pub fn utf8_non_ascii(buf: &mut Buffer) -> Result<()> {
+    match utf8_non_ascii_length(buf) {
+        Some(length) => {
+            buf.advance(length);
+            Ok(())
+        }
+        None => buf.error("expected a UTF-8 character"),
+    }
+}
@@ -4,7 +4,7 @@
 
 use std::fmt;
 
-use crate::syntax::{SyntaxError, Located, Location};
+use crate::{state::Errors, syntax::{SyntaxError, Located, Location}};
 
 pub fn maybe_ascii(ascii: &[u8]) -> MaybeAscii {
     MaybeAscii(ascii)
@@ -41,13 +41,18 @@ impl fmt::Debug for MaybeAscii<'_> {
 }
 
 pub trait SetOnce<T> {
-    fn set_once(&mut self, at: Location, header: &str, value: T) -> Result<(), Located<SyntaxError>>;
+    fn set_once(&mut self, errors: &mut Errors, at: Location, header: &str, value: T)
+    -> Result<(), Located<SyntaxError>>;
 }
 
 impl<T> SetOnce<T> for Option<T> {
-    fn set_once(&mut self, at: Location, header: &str, value: T) -> Result<(), Located<SyntaxError>> {
+    /// Set value of a header which may only appear once
+    ///
+    /// When parsing leniently the first occurrence wins.
+    fn set_once(&mut self, errors: &mut Errors, at: Location, header: &str, value: T)
+    -> Result<(), Located<SyntaxError>> {
         match self {
-            Some(_) => Err(Located::new(at, format!("duplicate header {header}"))),
+            Some(_) => errors.recover(Located::new(at, format!("duplicate header {header}"))),
             None => {
                 *self = Some(value);
                 Ok(())
@@ -56,9 +61,11 @@ impl<T> SetOnce<T> for Option<T> {
     }
 }
 
-/// Serialize `T` using `<T as std::string::ToString>`
+/// Serialize `T` using `<T as std::string::ToString>` and deserialize it using
+/// `<T as std::str::FromStr>`
 pub mod as_string {
-    use serde::{Serialize, Serializer};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+    use std::{fmt::Display, str::FromStr};
 
     pub fn serialize<T, S>(value: &T, se: S) -> Result<S::Ok, S::Error>
     where
@@ -67,4 +74,64 @@ pub mod as_string {
     {
         value.to_string().serialize(se)
     }
+
+    pub fn deserialize<'de, T, D>(de: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(de)?.parse().map_err(D::Error::custom)
+    }
+
+    /// Same as [`as_string`](self), but for optional values
+    pub mod option {
+        use serde::{Deserialize, Deserializer, de::Error};
+        use std::{fmt::Display, str::FromStr};
+
+        pub fn deserialize<'de, T, D>(de: D) -> Result<Option<T>, D::Error>
+        where
+            T: FromStr,
+            T::Err: Display,
+            D: Deserializer<'de>,
+        {
+            Option::<String>::deserialize(de)?
+                .map(|value| value.parse().map_err(D::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+/// Deserialize an optional [`Duration`](std::time::Duration) from a string
+/// such as `10s`, `500ms`, or `2m`; numbers without a unit are seconds
+pub mod duration {
+    use serde::{Deserialize, Deserializer, de::Error};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D>(de: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = match Option::<String>::deserialize(de)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let split = value.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number: f64 = number.parse()
+            .map_err(|_| D::Error::custom(format!("invalid duration {value:?}")))?;
+
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "" | "s" => number,
+            "m" => number * 60.0,
+            _ => return Err(D::Error::custom(format!("invalid duration unit {unit:?}"))),
+        };
+
+        Duration::try_from_secs_f64(seconds)
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("duration {value:?} out of range")))
+    }
 }
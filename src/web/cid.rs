@@ -0,0 +1,101 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Resolving `cid:` URLs (RFC 2392) in HTML parts
+
+use std::collections::HashMap;
+
+use crate::mime::{Entity, EntityData};
+use super::encode_path_segment;
+
+/// URLs of body parts, by their Content-ID
+pub struct ContentIds<'a> {
+    urls: HashMap<&'a str, String>,
+}
+
+impl<'a> ContentIds<'a> {
+    /// Find all parts with a Content-ID within a single message
+    ///
+    /// `root` is the body of a message (possibly one embedded in another),
+    /// and `path` is its path, as used in `/messages/:id/*number`. Messages
+    /// embedded in `root` are not searched, as their parts can't be
+    /// referenced from outside.
+    pub fn collect(message: &str, root: &'a Entity, path: &[usize]) -> Self {
+        let mut ids = ContentIds { urls: HashMap::new() };
+        ids.add(message, root, &mut path.to_vec());
+        ids
+    }
+
+    fn add(&mut self, message: &str, entity: &'a Entity, path: &mut Vec<usize>) {
+        if let Some(ref id) = entity.id {
+            let path = path.iter().map(|part| format!("/{part}")).collect::<String>();
+            self.urls.entry(id)
+                .or_insert_with(|| format!("/messages/{}{path}", encode_path_segment(message)));
+        }
+
+        if let EntityData::Multipart(ref mp) = entity.data {
+            for (index, part) in mp.parts.iter().enumerate() {
+                path.push(index);
+                self.add(message, part, path);
+                path.pop();
+            }
+        }
+    }
+
+    /// Replace `cid:` URLs in an HTML document with URLs of the parts they
+    /// refer to
+    ///
+    /// URLs are only recognized in attribute values and in CSS `url()`s.
+    /// References to parts which don't exist are left unchanged.
+    pub fn resolve(&self, html: &str) -> String {
+        let lower = html.to_ascii_lowercase();
+        let mut result = String::with_capacity(html.len());
+        let mut last = 0;
+
+        for (start, _) in lower.match_indices("cid:") {
+            if start < last || !html[..start].ends_with(['"', '\'', '(', '=']) {
+                continue;
+            }
+
+            let end = html[start..]
+                .find(|c: char| matches!(c, '"' | '\'' | ')' | '>') || c.is_ascii_whitespace())
+                .map_or(html.len(), |end| start + end);
+
+            if let Some(url) = self.urls.get(&*percent_decode(&html[start + 4..end])) {
+                result.push_str(&html[last..start]);
+                result.push_str(url);
+                last = end;
+            }
+        }
+
+        result.push_str(&html[last..]);
+        result
+    }
+}
+
+/// Decode %XX escapes in a URL
+fn percent_decode(value: &str) -> String {
+    let mut data = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                data.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                data.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&data).into_owned()
+}
@@ -0,0 +1,136 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Selecting messages matching query parameters
+
+use regex::Regex;
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+use crate::{smtp::ReversePath, state::Message, util};
+use super::search::Search;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Filter {
+    /// Phrase which must appear anywhere in the message
+    #[serde(default, deserialize_with = "util::as_string::option::deserialize")]
+    pub q: Option<Search>,
+    /// Sender's address, either in the From header or in the envelope
+    pub from: Option<String>,
+    /// Recipient's address, either in the To, Cc, or Bcc header, or among
+    /// envelope recipients
+    pub to: Option<String>,
+    /// Text which must appear in the subject
+    pub subject: Option<String>,
+    /// Regular expression which must match the subject
+    #[serde(default, deserialize_with = "util::as_string::option::deserialize")]
+    pub subject_regex: Option<Regex>,
+    /// Only messages sent at or after this time
+    #[serde(default, with = "time::serde::timestamp::option")]
+    pub since: Option<OffsetDateTime>,
+    /// Only messages sent at or before this time
+    #[serde(default, with = "time::serde::timestamp::option")]
+    pub until: Option<OffsetDateTime>,
+}
+
+/// Ordering and pagination of a list of messages
+#[derive(Debug, Default, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub sort: Sort,
+    /// Maximum number of messages to return
+    pub limit: Option<usize>,
+    /// Number of messages to skip
+    #[serde(default)]
+    pub offset: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum Sort {
+    /// Oldest messages first
+    #[default]
+    #[serde(rename = "date")]
+    DateAscending,
+    /// Newest messages first
+    #[serde(rename = "-date")]
+    DateDescending,
+}
+
+impl Filter {
+    /// Check whether a message matches this filter
+    pub fn matches(&self, message: &Message) -> bool {
+        if let Some(ref from) = self.from {
+            let in_headers = message.from.iter()
+                .any(|mailbox| mailbox.address.to_string().eq_ignore_ascii_case(from));
+            let in_envelope = match message.envelope.from {
+                ReversePath::Mailbox(ref mailbox) => mailbox.to_string().eq_ignore_ascii_case(from),
+                ReversePath::Null => false,
+            };
+
+            if !in_headers && !in_envelope {
+                return false;
+            }
+        }
+
+        if let Some(ref to) = self.to {
+            let in_headers = message.to.iter()
+                .chain(&message.cc)
+                .chain(&message.bcc)
+                .flat_map(|address| address.mailboxes())
+                .any(|mailbox| mailbox.address.to_string().eq_ignore_ascii_case(to));
+            let in_envelope = message.envelope.to.iter()
+                .any(|path| path.to_string().eq_ignore_ascii_case(to));
+
+            if !in_headers && !in_envelope {
+                return false;
+            }
+        }
+
+        if let Some(ref subject) = self.subject {
+            match message.subject {
+                Some(ref value) if value.contains(subject.as_str()) => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(ref regex) = self.subject_regex {
+            match message.subject {
+                Some(ref value) if regex.is_match(value) => {}
+                _ => return false,
+            }
+        }
+
+        if self.since.is_some_and(|since| message.date < since)
+        || self.until.is_some_and(|until| message.date > until) {
+            return false;
+        }
+
+        if let Some(ref search) = self.q {
+            if search.find(message).is_none() {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl Page {
+    /// Sort messages and select the requested page
+    pub fn apply<'a>(&self, mut messages: Vec<&'a Message>) -> Vec<&'a Message> {
+        // Ties are broken by ID, so that pages are stable.
+        match self.sort {
+            Sort::DateAscending =>
+                messages.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.id.cmp(&b.id))),
+            Sort::DateDescending =>
+                messages.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| b.id.cmp(&a.id))),
+        }
+
+        messages.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
@@ -6,28 +6,42 @@ use anyhow::Result;
 use axum::{
     AddExtensionLayer, Json, Router,
     body,
-    extract::{Extension, Path, ws},
-    http::{StatusCode, Response, header::CONTENT_TYPE},
-    response::IntoResponse,
+    extract::{Extension, Path, Query, rejection::QueryRejection, ws},
+    http::{StatusCode, Response, header::{CONTENT_DISPOSITION, CONTENT_TYPE}},
+    response::{Headers, IntoResponse},
     routing::get,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use std::{sync::Arc, net::{SocketAddr, Ipv4Addr}};
+use std::{sync::Arc, net::{SocketAddr, Ipv4Addr}, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::{
     config,
-    mail::{Mailbox, AddressOrGroup},
-    mime::{EntityData, ContentType, Entity, MultipartKind},
-    state::{StateRef, Message, MessageBody},
+    mail::{Mailbox, AddressOrGroup, HeaderField},
+    mime::{EntityData, ContentType, Disposition, Entity, MultipartKind, encoding},
+    state::{StateRef, Message, MessageBody, Envelope, Event, Trace},
     syntax::Located,
     util,
 };
 
+use self::{cid::ContentIds, filter::{Filter, Page}, search::Found};
+
+mod cid;
+mod filter;
+mod search;
+
+/// Header containing number of messages matching a query, before pagination
+const TOTAL_COUNT: &str = "X-Total-Count";
+
+/// How long to wait for a message when no timeout was given
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub async fn start(config: config::Http, state: StateRef) -> Result<()> {
     let app = Router::new()
-        .route("/messages", get(list_messages))
-        .route("/messages/:id", get(message))
+        .route("/messages", get(list_messages).delete(clear_messages))
+        .route("/messages/wait", get(wait_for_message))
+        .route("/messages/:id", get(message).delete(delete_message))
         .route("/messages/:id/*number", get(message_part))
         .route("/subscribe", get(message_stream))
         .route("/", get(index))
@@ -45,15 +59,29 @@ pub async fn start(config: config::Http, state: StateRef) -> Result<()> {
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct MessageData {
     id: String,
+    envelope: Envelope,
     #[serde(with = "time::serde::timestamp")]
     date: OffsetDateTime,
     from: Vec<Mailbox>,
+    sender: Option<Mailbox>,
+    reply_to: Vec<AddressOrGroup>,
     subject: Option<String>,
     to: Vec<AddressOrGroup>,
+    cc: Vec<AddressOrGroup>,
+    bcc: Vec<AddressOrGroup>,
+    in_reply_to: Vec<String>,
+    references: Vec<String>,
+    keywords: Vec<String>,
+    comments: Vec<String>,
+    trace: Vec<Trace>,
     body: BodyType,
     errors: Vec<Located<String>>,
+    /// Where the searched-for phrase was found
+    #[serde(rename = "match", skip_serializing_if = "Option::is_none")]
+    found: Option<Found>,
 }
 
 #[derive(Debug, Serialize)]
@@ -64,13 +92,28 @@ enum BodyType {
 }
 
 impl From<&'_ Message> for MessageData {
-    fn from(Message { id, date, from, subject, to, body, errors, .. }: &'_ Message) -> Self {
+    fn from(message: &'_ Message) -> Self {
+        let Message {
+            id, envelope, date, from, sender, reply_to, subject, to, cc, bcc, in_reply_to,
+            references, keywords, comments, trace, body, errors, ..
+        } = message;
+
         MessageData {
             id: id.clone(),
+            envelope: envelope.clone(),
             date: *date,
             from: from.clone(),
+            sender: sender.clone(),
+            reply_to: reply_to.clone(),
             subject: subject.clone(),
             to: to.clone(),
+            cc: cc.clone(),
+            bcc: bcc.clone(),
+            in_reply_to: in_reply_to.clone(),
+            references: references.clone(),
+            keywords: keywords.clone(),
+            comments: comments.clone(),
+            trace: trace.clone(),
             body: match body {
                 MessageBody::Unknown(_) => BodyType::Data,
                 MessageBody::Mime(ref mime) => match mime.data {
@@ -79,17 +122,117 @@ impl From<&'_ Message> for MessageData {
                 },
             },
             errors: errors.clone(),
+            found: None,
         }
     }
 }
 
-async fn list_messages(Extension(state): Extension<StateRef>) -> Json<Vec<MessageData>> {
-    Json(state.messages()
-        .await
-        .values()
+async fn list_messages(
+    Extension(state): Extension<StateRef>,
+    Query(filter): Query<Filter>,
+    Query(page): Query<Page>,
+) -> impl IntoResponse {
+    let messages = state.messages().await;
+    let matching = messages.values()
         .map(Arc::as_ref)
-        .map(MessageData::from)
-        .collect())
+        .filter(|message| filter.matches(message))
+        .collect::<Vec<_>>();
+    let total = matching.len();
+
+    let data = page.apply(matching)
+        .into_iter()
+        .map(|message| MessageData {
+            found: filter.q.as_ref().and_then(|search| search.find(message)),
+            ..MessageData::from(message)
+        })
+        .collect::<Vec<_>>();
+
+    (Headers([(TOTAL_COUNT, total.to_string())]), Json(data))
+}
+
+#[derive(Debug, Deserialize)]
+struct WaitQuery {
+    #[serde(default, deserialize_with = "util::duration::deserialize")]
+    timeout: Option<Duration>,
+}
+
+/// Wait until a message matching a filter is received
+///
+/// If such a message already exists it is returned immediately (the most
+/// recent one if there are many), otherwise this waits for a new message and
+/// responds with 408 if none arrives within the timeout.
+async fn wait_for_message(
+    Extension(state): Extension<StateRef>,
+    Query(filter): Query<Filter>,
+    query: Result<Query<WaitQuery>, QueryRejection>,
+) -> Result<Json<MessageData>, StatusCode> {
+    let timeout = match query {
+        Ok(Query(WaitQuery { timeout })) => timeout,
+        Err(err) => {
+            log::debug!("invalid wait query: {err}");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    // Subscribe before looking at existing messages, so that a message
+    // arriving in between is not missed.
+    let mut events = state.subscribe();
+
+    let find_existing = || async {
+        state.messages()
+            .await
+            .values()
+            .filter(|message| filter.matches(message))
+            .max_by_key(|message| message.date)
+            .cloned()
+    };
+
+    if let Some(message) = find_existing().await {
+        return Ok(Json(MessageData::from(&*message)));
+    }
+
+    let wait = async {
+        loop {
+            match events.recv().await {
+                Ok(Event::Received(message)) if filter.matches(&message) =>
+                    return Some(message),
+                Ok(_) => continue,
+                // Matching message may have been among those skipped.
+                Err(RecvError::Lagged(_)) => match find_existing().await {
+                    Some(message) => return Some(message),
+                    None => continue,
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT), wait).await {
+        Ok(Some(message)) => Ok(Json(MessageData::from(&*message))),
+        Ok(None) | Err(_) => Err(StatusCode::REQUEST_TIMEOUT),
+    }
+}
+
+async fn clear_messages(Extension(state): Extension<StateRef>) -> StatusCode {
+    match state.clear().await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            log::error!("could not delete messages: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn delete_message(Extension(state): Extension<StateRef>, Path(id): Path<String>)
+-> StatusCode {
+    match state.delete_message(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            log::error!("could not delete message {id}: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
 }
 
 async fn message(Extension(state): Extension<StateRef>, Path(id): Path<String>)
@@ -108,70 +251,256 @@ async fn message(Extension(state): Extension<StateRef>, Path(id): Path<String>)
     })
 }
 
+fn message_raw(message: &Message) -> Response<body::Full<body::Bytes>> {
+    Response::builder()
+        .header(CONTENT_TYPE, "message/rfc822")
+        .header(CONTENT_DISPOSITION, attachment(&format!("{}.eml", message.id)))
+        .body(to_bytes(&message.raw))
+        .unwrap()
+}
+
 fn entity_to_response(entity: &Entity) -> Response<body::Full<body::Bytes>> {
     match entity.data {
         EntityData::Text(ref text) => Response::builder()
-            .header(CONTENT_TYPE, &entity.content_type)
+            .header(CONTENT_TYPE, text_content_type(entity))
             .body(to_bytes(text.as_bytes()))
             .unwrap(),
         EntityData::Binary(ref data) => Response::builder()
             .header(CONTENT_TYPE, &entity.content_type)
             .body(to_bytes(data))
             .unwrap(),
-        EntityData::Multipart(ref mp) => Response::builder()
-            .header(CONTENT_TYPE, ContentType::APPLICATION_JSON)
-            .body(body::Full::new(serde_json::to_vec(&MultipartDesc {
-                kind: mp.kind,
-                parts: mp.parts.iter().map(|entity| PartDesc {
-                    content_type: &entity.content_type,
-                }).collect(),
-            }).unwrap().into()))
-            .unwrap(),
+        EntityData::Multipart(ref mp) => to_json(&MultipartDesc {
+            kind: &mp.kind,
+            parts: mp.parts.iter().map(PartDesc::from).collect(),
+        }),
+        EntityData::Message(ref message) => to_json(&MessageDesc {
+            headers: &message.headers,
+            body: PartDesc::from(&**message),
+        }),
     }
 }
 
+/// Content type of a text part, as it is served
+///
+/// Text is decoded when a message is parsed, so it is always sent as UTF-8,
+/// regardless of the charset it was received in.
+fn text_content_type(entity: &Entity) -> ContentType {
+    entity.content_type.clone().with_parameter("charset", "utf-8")
+}
+
 #[derive(Serialize)]
 struct MultipartDesc<'a> {
-    kind: MultipartKind,
+    #[serde(flatten)]
+    kind: &'a MultipartKind,
     parts: Vec<PartDesc<'a>>,
 }
 
+/// Description of an embedded message
+#[derive(Serialize)]
+struct MessageDesc<'a> {
+    headers: &'a [HeaderField],
+    body: PartDesc<'a>,
+}
+
 #[derive(Serialize)]
 struct PartDesc<'a> {
     #[serde(with = "util::as_string", rename = "contentType")]
     content_type: &'a ContentType,
+    #[serde(rename = "contentId", skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disposition: Option<&'a Disposition>,
 }
 
-async fn message_part(Extension(state): Extension<StateRef>, Path((id, path)): Path<(String, String)>)
--> Result<impl IntoResponse, StatusCode> {
+impl<'a> From<&'a Entity> for PartDesc<'a> {
+    fn from(entity: &'a Entity) -> Self {
+        PartDesc {
+            content_type: &entity.content_type,
+            id: entity.id.as_deref(),
+            description: entity.description.as_deref(),
+            filename: entity.filename.as_deref(),
+            disposition: entity.disposition.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartQuery {
+    /// Replace `cid:` URLs in HTML parts with URLs of the parts they refer to
+    #[serde(default)]
+    resolve_cids: bool,
+}
+
+async fn message_part(
+    Extension(state): Extension<StateRef>,
+    Path((id, path)): Path<(String, String)>,
+    Query(query): Query<PartQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
     let message = match state.get_message(&id).await {
         Some(message) => message,
         _ => return Err(StatusCode::NOT_FOUND),
     };
 
+    // /messages/:id/raw, /messages/:id/headers, and /messages/:id/attachments
+    // can't be routed separately, as they would conflict with this route.
+    if path == "/raw" {
+        return Ok(message_raw(&message));
+    }
+
+    if path == "/headers" {
+        return Ok(to_json(&message.headers));
+    }
+
+    if path == "/attachments" {
+        let mut attachments = vec![];
+        if let MessageBody::Mime(ref entity) = message.body {
+            find_attachments(&message.id, entity, &mut vec![], &mut attachments);
+        }
+        return Ok(to_json(&attachments));
+    }
+
+    let (path, view) = if let Some(path) = path.strip_suffix("/headers") {
+        (path, PartView::Headers)
+    } else if let Some(path) = path.strip_suffix("/download") {
+        (path, PartView::Download)
+    } else {
+        (path.as_str(), PartView::Content)
+    };
+
     let mut entity = match message.body {
-        MessageBody::Mime(ref entity) => entity,
+        MessageBody::Mime(ref entity) => &**entity,
         _ => return Err(StatusCode::NOT_FOUND),
     };
+    // Body of the (possibly embedded) message containing the part, and its
+    // path
+    let mut root = (entity, 0);
+    let mut parts = vec![];
 
     for part in path.split('/').skip(1) {
         let part: usize = match part.parse() {
             Ok(part) => part,
             Err(_) => return Err(StatusCode::NOT_FOUND),
         };
+        parts.push(part);
 
-        let mp = match entity.data {
-            EntityData::Multipart(ref mp) => mp,
+        entity = match entity.data {
+            EntityData::Multipart(ref mp) => match mp.parts.get(part) {
+                Some(part) => part,
+                _ => return Err(StatusCode::NOT_FOUND),
+            },
+            // Body of an embedded message is its only part.
+            EntityData::Message(ref message) if part == 0 => {
+                root = (message, parts.len());
+                message
+            }
             _ => return Err(StatusCode::NOT_FOUND),
         };
+    }
 
-        entity = match mp.parts.get(part) {
-            Some(part) => part,
-            _ => return Err(StatusCode::NOT_FOUND),
-        };
+    match view {
+        PartView::Content => match entity.data {
+            EntityData::Text(ref html)
+            if query.resolve_cids && entity.content_type.is("text", "html") => {
+                let ids = ContentIds::collect(&message.id, root.0, &parts[..root.1]);
+                Ok(Response::builder()
+                    .header(CONTENT_TYPE, text_content_type(entity))
+                    .body(to_bytes(ids.resolve(html).as_bytes()))
+                    .unwrap())
+            }
+            _ => Ok(entity_to_response(entity)),
+        },
+        PartView::Headers => Ok(to_json(&entity.headers)),
+        PartView::Download => {
+            let (data, content_type) = match entity.data {
+                EntityData::Text(ref text) => (text.as_bytes(), text_content_type(entity)),
+                EntityData::Binary(ref data) => (&data[..], entity.content_type.clone()),
+                _ => return Err(StatusCode::NOT_FOUND),
+            };
+            let filename = match entity.filename {
+                Some(ref filename) => filename.clone(),
+                None => format!("{}-{}", message.id, path.trim_start_matches('/').replace('/', "-")),
+            };
+
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header(CONTENT_DISPOSITION, attachment(&filename))
+                .body(to_bytes(data))
+                .unwrap())
+        }
     }
+}
+
+/// What to return for a MIME part
+enum PartView {
+    /// Its contents
+    Content,
+    /// Its header fields
+    Headers,
+    /// Its contents, as a file to be downloaded
+    Download,
+}
 
-    Ok(entity_to_response(entity))
+/// File attached to a message
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentDesc<'a> {
+    /// Path of the MIME part, as used in `/messages/:id/*number`
+    part: String,
+    filename: Option<&'a str>,
+    #[serde(with = "util::as_string")]
+    content_type: &'a ContentType,
+    /// Size of the decoded contents, in octets
+    size: usize,
+    /// URL from which the file can be downloaded
+    url: String,
+}
+
+/// Find all body parts which are meant to be presented as attachments
+///
+/// Parts are included if their disposition is `attachment`, or if they
+/// suggest a file name.
+fn find_attachments<'a>(
+    id: &str,
+    entity: &'a Entity,
+    path: &mut Vec<usize>,
+    attachments: &mut Vec<AttachmentDesc<'a>>,
+) {
+    let size = match entity.data {
+        EntityData::Text(ref text) => text.len(),
+        EntityData::Binary(ref data) => data.len(),
+        EntityData::Multipart(ref mp) => {
+            for (index, part) in mp.parts.iter().enumerate() {
+                path.push(index);
+                find_attachments(id, part, path, attachments);
+                path.pop();
+            }
+            return;
+        }
+        EntityData::Message(ref message) => {
+            path.push(0);
+            find_attachments(id, message, path, attachments);
+            path.pop();
+            return;
+        }
+    };
+
+    let is_attachment = entity.disposition.as_ref().is_some_and(Disposition::is_attachment);
+
+    if is_attachment || entity.filename.is_some() {
+        let part = path.iter().map(usize::to_string).collect::<Vec<_>>().join("/");
+        attachments.push(AttachmentDesc {
+            url: format!("/messages/{}/{part}/download", encode_path_segment(id)),
+            part,
+            filename: entity.filename.as_deref(),
+            content_type: &entity.content_type,
+            size,
+        });
+    }
 }
 
 async fn message_stream(
@@ -181,16 +510,38 @@ async fn message_stream(
     ws.on_upgrade(|socket| handle_socket(state, socket))
 }
 
+/// Notification sent to WebSocket listeners
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Notification {
+    /// A new message was received
+    Message(Box<MessageData>),
+    /// Messages were deleted
+    Deleted {
+        ids: Vec<String>,
+    },
+}
+
+impl From<Event> for Notification {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::Received(message) =>
+                Notification::Message(Box::new(MessageData::from(&*message))),
+            Event::Deleted(ids) => Notification::Deleted { ids },
+        }
+    }
+}
+
 async fn handle_socket(state: StateRef, mut socket: ws::WebSocket) {
     log::debug!("listener connected");
 
-    let mut messages = state.subscribe();
+    let mut events = state.subscribe();
 
     loop {
         tokio::select! {
-            msg = messages.recv() => {
+            msg = events.recv() => {
                 let msg = match msg {
-                    Ok(msg) => MessageData::from(&*msg),
+                    Ok(event) => Notification::from(event),
                     Err(_) => break,
                 };
 
@@ -248,3 +599,54 @@ impl IntoResponse for &'_ File {
 fn to_bytes(bytes: &[u8]) -> body::Full<body::Bytes> {
     body::Full::new(body::Bytes::copy_from_slice(bytes))
 }
+
+fn to_json(value: &impl Serialize) -> Response<body::Full<body::Bytes>> {
+    Response::builder()
+        .header(CONTENT_TYPE, ContentType::APPLICATION_JSON)
+        .body(body::Full::new(serde_json::to_vec(value).unwrap().into()))
+        .unwrap()
+}
+
+/// Format value of a Content-Disposition header for downloading a file
+fn attachment(filename: &str) -> String {
+    // Message IDs often contain characters which are not allowed in a quoted
+    // string, or which file systems don't like.
+    let filename = filename.chars()
+        .map(|c| match c {
+            '"' | '\\' | '/' | ':' | '<' | '>' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    let ascii = filename.chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect::<String>();
+
+    if ascii == filename {
+        return format!("attachment; filename=\"{filename}\"");
+    }
+
+    // Non-ASCII names are sent as an RFC 5987 extended value, with an ASCII
+    // fallback for older clients.
+    format!("attachment; filename=\"{ascii}\"; filename*={}", encoding::encode_extended_value(&filename))
+}
+
+/// Percent-encode a single segment of a URL path
+///
+/// Message and Content IDs may contain characters such as `/`, `?`, `#`, and
+/// `%` which would otherwise change the meaning of a URL.
+fn encode_path_segment(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+            | b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@'
+                => result.push(char::from(byte)),
+            _ => result.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    result
+}
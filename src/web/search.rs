@@ -0,0 +1,132 @@
+// Copyright 2022 OpenStax Poland
+// Licensed under the MIT license. See LICENSE file in the project root for
+// full license text.
+
+//! Full-text search in messages
+
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::{
+    mime::{Entity, EntityData},
+    state::{Message, MessageBody},
+};
+
+/// Number of characters included in a snippet on each side of a match
+const CONTEXT: usize = 40;
+
+/// Case-insensitive search for a phrase
+#[derive(Debug)]
+pub struct Search {
+    regex: Regex,
+}
+
+/// Place in a message where a phrase was found
+#[derive(Debug, Serialize)]
+pub struct Found {
+    /// Path of the MIME part in which the phrase was found, as used in
+    /// `/messages/:id/*number`, or `None` if it was found in headers or in
+    /// the top-level body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<String>,
+    /// Fragment of text surrounding the match
+    pub snippet: String,
+}
+
+impl FromStr for Search {
+    type Err = regex::Error;
+
+    fn from_str(phrase: &str) -> Result<Self, Self::Err> {
+        let regex = RegexBuilder::new(&regex::escape(phrase))
+            .case_insensitive(true)
+            .build()?;
+        Ok(Search { regex })
+    }
+}
+
+impl Search {
+    /// Find first occurrence of the phrase in a message
+    ///
+    /// Subject, addresses, and all text parts of the body are searched, in
+    /// that order.
+    pub fn find(&self, message: &Message) -> Option<Found> {
+        let addresses = message.from.iter()
+            .chain(message.to.iter().flat_map(|address| address.mailboxes()))
+            .map(|mailbox| match mailbox.name {
+                Some(ref name) => format!("{name} <{}>", mailbox.address),
+                None => mailbox.address.to_string(),
+            });
+
+        for field in message.subject.iter().cloned().chain(addresses) {
+            if let Some(snippet) = self.find_in(&field) {
+                return Some(Found { part: None, snippet });
+            }
+        }
+
+        match message.body {
+            MessageBody::Unknown(ref text) => self.find_in(text)
+                .map(|snippet| Found { part: None, snippet }),
+            MessageBody::Mime(ref entity) => self.find_in_entity(entity, &mut vec![]),
+        }
+    }
+
+    fn find_in_entity(&self, entity: &Entity, path: &mut Vec<usize>) -> Option<Found> {
+        match entity.data {
+            EntityData::Text(ref text) => self.find_in(text).map(|snippet| Found {
+                part: (!path.is_empty()).then(|| format_path(path)),
+                snippet,
+            }),
+            EntityData::Binary(_) => None,
+            EntityData::Multipart(ref mp) => mp.parts.iter().enumerate().find_map(|(inx, part)| {
+                path.push(inx);
+                let found = self.find_in_entity(part, path);
+                path.pop();
+                found
+            }),
+            EntityData::Message(ref message) => {
+                path.push(0);
+                let found = self.find_in_entity(message, path);
+                path.pop();
+                found
+            }
+        }
+    }
+
+    /// Find phrase in text and return a snippet around it
+    fn find_in(&self, text: &str) -> Option<String> {
+        let found = self.regex.find(text)?;
+
+        let start = text[..found.start()].char_indices()
+            .rev()
+            .nth(CONTEXT - 1)
+            .map_or(0, |(inx, _)| inx);
+        let end = text[found.end()..].char_indices()
+            .nth(CONTEXT)
+            .map_or(text.len(), |(inx, _)| found.end() + inx);
+
+        let mut snippet = String::new();
+
+        if start > 0 {
+            snippet.push('…');
+        }
+
+        // Snippets are shown on a single line.
+        for (inx, word) in text[start..end].split_whitespace().enumerate() {
+            if inx > 0 {
+                snippet.push(' ');
+            }
+            snippet.push_str(word);
+        }
+
+        if end < text.len() {
+            snippet.push('…');
+        }
+
+        Some(snippet)
+    }
+}
+
+fn format_path(path: &[usize]) -> String {
+    path.iter().map(usize::to_string).collect::<Vec<_>>().join("/")
+}